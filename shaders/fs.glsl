@@ -1,10 +1,23 @@
 in vec2 pass_uv;
+flat in vec2 pass_tile;
 in vec3 color;
 
 out vec4 out_color;
 
 uniform sampler2D terrain_tex;
+uniform vec2 tile_size;
+uniform float alpha_cutoff;
 
 void main() {
-   out_color = vec4(color, 1.0) * texture(terrain_tex, pass_uv);
+   // Repeat the tile once per block covered by the quad.
+   vec2 uv = pass_tile + fract(pass_uv) * tile_size;
+   
+   vec4 texel = texture(terrain_tex, uv);
+   
+   // Cutout blocks, such as leaves, have holes in their texture.
+   if (texel.a < alpha_cutoff) {
+      discard;
+   }
+   
+   out_color = vec4(color, 1.0) * texel;
 }
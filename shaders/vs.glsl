@@ -1,40 +1,75 @@
-layout (location = 0) in vec3 pos;
-layout (location = 1) in vec2 uv;
-layout (location = 2) in uint facenum;
+// Vertices are packed as described in src/terrain/vertex.rs.
+layout (location = 0) in uint packed_pos;
+layout (location = 1) in uint attributes;
 
 out vec2 pass_uv;
+flat out vec2 pass_tile;
 out vec3 color;
 
 uniform mat4 model_matrix;
 uniform mat4 view_matrix;
 uniform mat4 projection_matrix;
+uniform vec2 tile_size;
 
 void main() {
+    // The position is in sixteenths of a block.
+    vec3 pos = vec3(packed_pos & 1023u,
+                    (packed_pos >> 10u) & 1023u,
+                    (packed_pos >> 20u) & 1023u) / 16.0;
+    uint facenum = attributes & 7u;
+    uint occlusion = (attributes >> 3u) & 3u;
+    uint light = (attributes >> 8u) & 255u;
+    uint tile = attributes >> 16u;
+    
     gl_Position = projection_matrix * view_matrix * model_matrix * vec4(pos, 1.0);
     
-    pass_uv = uv;
+    // Tiles are laid out from left to right, then top to bottom.
+    uint columns = uint(round(1.0 / tile_size.x));
+    pass_tile = vec2(tile % columns, tile / columns) * tile_size;
     
+    // The texture runs along the face, and the fragment shader
+    // repeats it once per block. Crosses run along the diagonal,
+    // like the right face.
     switch (facenum) {
         case 0u:
+            pass_uv = vec2(-pos.x, -pos.y);
             color = vec3(0.8, 0.8, 0.8);
             break;
         case 1u:
+            pass_uv = vec2(pos.x, -pos.y);
             color = vec3(1.0, 1.0, 1.0);
             break;
         case 2u:
+            pass_uv = vec2(pos.x, pos.z);
             color = vec3(0.9, 0.9, 0.9);
             break;
         case 3u:
+            pass_uv = vec2(pos.x, -pos.z);
             color = vec3(0.7, 0.7, 0.7);
             break;
         case 4u:
+            pass_uv = vec2(pos.z, -pos.y);
             color = vec3(0.8, 0.8, 0.8);
             break;
         case 5u:
+            pass_uv = vec2(-pos.z, -pos.y);
             color = vec3(0.8, 0.8, 0.8);
             break;
+        case 6u:
+            pass_uv = vec2(-pos.z, -pos.y);
+            color = vec3(1.0, 1.0, 1.0);
+            break;
         default:
+            pass_uv = vec2(0.0, 0.0);
             color = vec3(1.0, 0.0, 0.0);
             break;
     }
+    
+    // Darken occluded corners.
+    color *= mix(0.4, 1.0, float(occlusion) / 3.0);
+    
+    // Sky light is in the high 4 bits, block light in the low 4.
+    // Each level below the brightest dims the color by a fifth.
+    uint level = max(light >> 4u, light & 15u);
+    color *= pow(0.8, float(15u - level));
 }
@@ -0,0 +1,82 @@
+//! Writes maps of a world from above, without opening a window.
+//!
+//! Usage: `worldmap <seed> [<x> <z> <width> <depth>]`
+//!
+//! The area defaults to 1024 by 1024 blocks around the origin.
+//! The width and depth must be positive.
+//! The heights are written to `heightmap.png`, the block on top
+//! of every column to `surface.png`, and the biome that weighs
+//! the most in every column to `biomes.png`.
+
+extern crate luminance_procedural_world;
+
+use std::env;
+use std::fs::File;
+use std::io::BufWriter;
+use std::process;
+use luminance_procedural_world::terrain::{BiomeMap, BlockColors, BlockRegistry, MapArea,
+                                          SurfaceMap, WorldGen, WorldGenConfig};
+
+const WORLDGEN_CONFIG: &str = "data/worldgen.toml";
+const USAGE: &str = "Usage: worldmap <seed> [<x> <z> <width> <depth>]\n\
+                     The seed is an unsigned integer, and the width and depth are positive.";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    
+    let (seed, area) = match parse_args(&args) {
+        Some(parsed) => parsed,
+        None => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        },
+    };
+    
+    let registry = BlockRegistry::load(File::open("data/blocks.toml").unwrap()).unwrap();
+    let colors = BlockColors::load(File::open("data/atlas.png").unwrap(),
+                                   File::open("data/atlas.txt").unwrap(),
+                                   &registry).unwrap();
+    
+    let config = File::open(WORLDGEN_CONFIG).and_then(|file| {
+        WorldGenConfig::load(file, &registry)
+    });
+    let config = config.unwrap_or_else(|e| {
+        panic!("Could not load {}: {}", WORLDGEN_CONFIG, e)
+    });
+    
+    let gen = WorldGen::with_config(seed, &registry, config);
+    let map = SurfaceMap::sample(&gen, area);
+    
+    map.write_heightmap(BufWriter::new(File::create("heightmap.png").unwrap())).unwrap();
+    map.write_blocks(BufWriter::new(File::create("surface.png").unwrap()), &colors).unwrap();
+    
+    let biomes = BiomeMap::sample(&gen, area);
+    biomes.write(BufWriter::new(File::create("biomes.png").unwrap())).unwrap();
+    
+    let (lowest, highest) = map.height_range();
+    println!("heights: {} to {}", lowest, highest);
+}
+
+// Read the seed and the area to map from the arguments.
+// Returns `None` if they are not as described in `USAGE`.
+fn parse_args(args: &[String]) -> Option<(u64, MapArea)> {
+    let seed = args.first()?.parse().ok()?;
+    
+    let area = match args.len() {
+        1 => MapArea {
+            x: -512,
+            z: -512,
+            width: 1024,
+            depth: 1024,
+        },
+        5 => MapArea {
+            x: args[1].parse().ok()?,
+            z: args[2].parse().ok()?,
+            width: args[3].parse().ok().filter(|&w| w > 0)?,
+            depth: args[4].parse().ok().filter(|&d| d > 0)?,
+        },
+        _ => return None,
+    };
+    
+    Some((seed, area))
+}
@@ -55,6 +55,14 @@ impl Camera {
         &mut self.rot
     }
     
+    /// Calculate the unit vector along which the camera looks.
+    pub fn direction(&self) -> (f32, f32, f32) {
+        let (sin_x, cos_x) = (self.rot.x.sin(), self.rot.x.cos());
+        let (sin_y, cos_y) = (self.rot.y.sin(), self.rot.y.cos());
+        
+        (-sin_y * cos_x, sin_x, -cos_y * cos_x)
+    }
+    
     /// Calculate the frustum of the camera. Somewhat expensive.
     pub fn frustum(&self) -> Frustum {
         Frustum::new(&self.projection_matrix, &self.to_matrix())
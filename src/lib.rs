@@ -8,8 +8,12 @@ extern crate luminance;
 extern crate luminance_glfw;
 extern crate noise;
 extern crate png;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate toml;
 
-pub use viewer::Viewer;
+pub use viewer::{HeightmapFile, Viewer, WorldKind};
 
 pub mod camera;
 #[macro_use]
@@ -1,5 +1,82 @@
 extern crate luminance_procedural_world;
 
+use std::env;
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+use luminance_procedural_world::WorldKind;
+
+const USAGE: &str = "\
+Usage: luminance_procedural_world [<seed>] [<kind>]
+       luminance_procedural_world [<seed>] <heightmap> [<horizontal scale> \
+[<vertical scale> [clamp|tile]]]
+The seed is an unsigned integer, and is only used by noise worlds. Without
+one, a new seed is picked from the clock. The kind is noise, superflat or
+debug, and defaults to noise. A heightmap is the path of a file ending in
+.png or .asc, whose samples are the horizontal scale wide in blocks (a
+positive number) and the vertical scale high in blocks (a number).";
+
 fn main() {
-    luminance_procedural_world::Viewer::run();
+    let args: Vec<String> = env::args().skip(1).collect();
+    
+    let (seed, kind) = match parse_args(&args) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            process::exit(2);
+        },
+    };
+    
+    let seed = seed.unwrap_or_else(|| {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        now.as_secs() ^ now.subsec_nanos() as u64
+    });
+    
+    if kind == WorldKind::Noise {
+        println!("seed: {}", seed);
+    }
+    
+    luminance_procedural_world::Viewer::run(seed, kind);
+}
+
+// Read the seed and the kind of world from the arguments,
+// as described in `USAGE`.
+fn parse_args(args: &[String]) -> Result<(Option<u64>, WorldKind), String> {
+    let mut args = args.iter().peekable();
+    
+    // The seed comes first, if there is one.
+    let seed = match args.peek().and_then(|arg| arg.parse().ok()) {
+        Some(seed) => {
+            args.next();
+            Some(seed)
+        },
+        None => None,
+    };
+    
+    let mut kind = match args.next() {
+        Some(arg) => arg.parse()?,
+        None => WorldKind::Noise,
+    };
+    
+    // A heightmap may be followed by its scales, and by
+    // what lies beyond its edges.
+    if let WorldKind::Heightmap(ref mut file) = kind {
+        if let Some(arg) = args.next() {
+            file.horizontal_scale = arg.parse().ok().filter(|&scale: &f32| scale > 0.)
+                                       .ok_or("the horizontal scale must be a positive number")?;
+        }
+        if let Some(arg) = args.next() {
+            file.vertical_scale = Some(arg.parse().map_err(|_| {
+                "the vertical scale must be a number"
+            })?);
+        }
+        if let Some(arg) = args.next() {
+            file.edges = arg.parse()?;
+        }
+    }
+    
+    if let Some(arg) = args.next() {
+        return Err(format!("unexpected argument {:?}", arg));
+    }
+    
+    Ok((seed, kind))
 }
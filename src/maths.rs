@@ -11,12 +11,16 @@ use luminance::linear::M44;
 /// Simply plug in 16 values as one would on paper.
 /// # Example
 /// ```
-/// let identity = mat4 [
+/// # #[macro_use] extern crate luminance_procedural_world;
+/// # fn main() {
+/// let identity = mat4! [
 ///     1., 0., 0., 0.,
 ///     0., 1., 0., 0.,
 ///     0., 0., 1., 0.,
 ///     0., 0., 0., 1.,
 /// ];
+/// # assert_eq!(identity[1], [0., 1., 0., 0.]);
+/// # }
 /// ```
 #[macro_export]
 macro_rules! mat4 {
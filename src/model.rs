@@ -5,7 +5,7 @@ use std::rc::Rc;
 use luminance::framebuffer::Framebuffer;
 use luminance::linear::M44;
 use luminance::vertex;
-use luminance::pixel::RGB32F;
+use luminance::pixel::RGBA32F;
 use luminance::tess::Tess;
 use luminance::texture::{Dim2, Flat, Texture};
 use luminance_glfw::GLFWDevice;
@@ -17,13 +17,12 @@ use maths::{ToMatrix, Translation};
 /// a representation of a 3D object.
 /// # Generic type parameters
 /// * **V**: The type of vertex to use with the tesselation.
-
 pub struct Model<V> {
     /// The model's vertex data.
     pub tess: Tess<V>,
     
     /// The texture for the model.
-    pub tex: Rc<(Texture<Flat, Dim2, RGB32F>, OutputInfo)>,
+    pub tex: Rc<(Texture<Flat, Dim2, RGBA32F>, OutputInfo)>,
     
     /// The translation that should
     /// be applied to the model.
@@ -32,12 +31,12 @@ pub struct Model<V> {
 
 impl<V: vertex::Vertex> Model<V> {
     /// Create a new model. The model will be centered at the origin.
-    pub fn new(tess: Tess<V>, tex: Rc<(Texture<Flat, Dim2, RGB32F>, OutputInfo)>) -> Model<V> {
+    pub fn new(tess: Tess<V>, tex: Rc<(Texture<Flat, Dim2, RGBA32F>, OutputInfo)>) -> Model<V> {
         Self::with_translation(tess, tex, Translation::new(0., 0., 0.))
     }
     
     /// Create a new model with the supplied translation.
-    pub fn with_translation(tess: Tess<V>, tex: Rc<(Texture<Flat, Dim2, RGB32F>, OutputInfo)>,
+    pub fn with_translation(tess: Tess<V>, tex: Rc<(Texture<Flat, Dim2, RGBA32F>, OutputInfo)>,
                             translation: Translation) -> Model<V> {
         Model {
             tess,
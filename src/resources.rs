@@ -2,13 +2,23 @@
 
 use std::fs::File;
 use std::rc::Rc;
-use luminance::pixel::RGB32F;
+use std::sync::Arc;
+use luminance::pixel::RGBA32F;
 use luminance::texture::{Dim2, Flat, MagFilter, MinFilter, Sampler, Texture};
 use png::{self, Decoder, OutputInfo};
+use terrain::{Atlas, BlockRegistry};
 
 /// A simple resource manager that can load and provide resources.
 pub struct Resources {
-    terrain_tex: Rc<(Texture<Flat, Dim2, RGB32F>, OutputInfo)>,
+    terrain_tex: Rc<(Texture<Flat, Dim2, RGBA32F>, OutputInfo)>,
+    terrain_atlas: Arc<Atlas>,
+    block_registry: Arc<BlockRegistry>,
+}
+
+impl Default for Resources {
+    fn default() -> Resources {
+        Resources::new()
+    }
 }
 
 impl Resources {
@@ -17,41 +27,71 @@ impl Resources {
     /// This constructor panics if the resources
     /// could not be loaded from disk.
     pub fn new() -> Resources {
+        let block_registry = Arc::new(BlockRegistry::load(File::open("data/blocks.toml").unwrap())
+                                                          .unwrap());
+        let terrain_tex = Rc::new(Self::load_texture(File::open("data/atlas.png").unwrap()));
+        let terrain_atlas = Arc::new(Atlas::load(File::open("data/atlas.txt").unwrap(),
+                                                 &terrain_tex.1, &block_registry).unwrap());
+        
         Resources {
-            terrain_tex: Rc::new(Self::load_texture(File::open("data/atlas.png").unwrap())),
+            terrain_tex,
+            terrain_atlas,
+            block_registry,
         }
     }
     
     /// Get terrain texture.
-    pub fn terrain_tex(&self) -> Rc<(Texture<Flat, Dim2, RGB32F>, OutputInfo)> {
+    pub fn terrain_tex(&self) -> Rc<(Texture<Flat, Dim2, RGBA32F>, OutputInfo)> {
         self.terrain_tex.clone()
     }
     
-    fn load_texture(file: File) -> (Texture<Flat, Dim2, RGB32F>, OutputInfo) {
+    /// Get the layout of the terrain texture.
+    /// It may be shared with other threads.
+    pub fn terrain_atlas(&self) -> &Arc<Atlas> {
+        &self.terrain_atlas
+    }
+    
+    /// Get the kinds of blocks in the world.
+    /// They may be shared with other threads.
+    pub fn block_registry(&self) -> &Arc<BlockRegistry> {
+        &self.block_registry
+    }
+    
+    fn load_texture(file: File) -> (Texture<Flat, Dim2, RGBA32F>, OutputInfo) {
         let png_decoder = Decoder::new(file);
         let (png_info, mut png_reader) = png_decoder.read_info().unwrap();
-        assert_eq!(png_info.color_type, png::ColorType::RGB);
         assert_eq!(png_info.bit_depth, png::BitDepth::Eight);
         let mut png_data = vec![0; png_info.buffer_size()];
         png_reader.next_frame(&mut png_data).unwrap();
         
+        // Images without an alpha channel are fully opaque.
+        let channels = match png_info.color_type {
+            png::ColorType::RGB => 3,
+            png::ColorType::RGBA => 4,
+            color_type => panic!("Unsupported texture color type: {:?}", color_type),
+        };
+        
         //println!("size: {:?}", (png_info.width, png_info.height));
-        assert_eq!(png_info.buffer_size() % 3, 0);
-        let mut image = Vec::with_capacity(png_info.buffer_size() / 3);
-        for i in 0..(png_info.buffer_size() / 3) {
-            let x = i * 3;
+        assert_eq!(png_info.buffer_size() % channels, 0);
+        let mut image = Vec::with_capacity(png_info.buffer_size() / channels);
+        for i in 0..(png_info.buffer_size() / channels) {
+            let x = i * channels;
+            let alpha = if channels == 4 { png_data[x + 3] } else { 255 };
             
             //println!("data: {:?}", &[png_data[x], png_data[x + 1], png_data[x + 2]]);
             image.push((png_data[x]     as f32 / 255.,
                         png_data[x + 1] as f32 / 255.,
-                        png_data[x + 2] as f32 / 255.));
+                        png_data[x + 2] as f32 / 255.,
+                        alpha           as f32 / 255.));
         }
         
-        let mut sampler = Sampler::default();
-        sampler.min_filter = MinFilter::Nearest;
-        sampler.mag_filter = MagFilter::Nearest;
+        let sampler = Sampler {
+            min_filter: MinFilter::Nearest,
+            mag_filter: MagFilter::Nearest,
+            ..Sampler::default()
+        };
         
-        let tex = Texture::<Flat, Dim2, RGB32F>::new(
+        let tex = Texture::<Flat, Dim2, RGBA32F>::new(
                 [png_info.width, png_info.height], 0, &sampler).unwrap();
         tex.upload(false, &image);
         
@@ -0,0 +1,163 @@
+//! The layout of the terrain texture atlas, and the
+//! tile used for each face of each block.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read};
+use png::OutputInfo;
+use super::mesh_gen::{Face, FACES};
+use super::registry::BlockRegistry;
+use super::voxel::BlockId;
+
+/// The size of one square tile in the atlas, in pixels.
+pub const TILE_SIZE: u32 = 16;
+
+/// Maps every face of every block to a tile of the atlas.
+pub struct Atlas {
+    tile_size: [f32; 2],
+    columns: u32,
+    tiles: HashMap<(BlockId, Face), u32>,
+}
+
+impl Atlas {
+    /// Read the atlas layout, which names each tile in order.
+    /// `texture_info` describes the atlas image, and `registry`
+    /// names the tiles that each block uses.
+    /// # Errors
+    /// Fails if the layout cannot be read, if it names a tile twice
+    /// or names more tiles than the image holds, or if a block uses
+    /// a tile that the layout does not name.
+    pub fn load<R: Read>(layout: R, texture_info: &OutputInfo,
+                         registry: &BlockRegistry) -> io::Result<Atlas> {
+        let mut names = HashMap::new();
+        
+        for line in BufReader::new(layout).lines() {
+            let line = line?;
+            let name = line.trim();
+            if name.is_empty() || name.starts_with('#') {
+                continue;
+            }
+            
+            let index = names.len() as u32;
+            if names.insert(name.to_string(), index).is_some() {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          format!("atlas layout names {:?} twice", name)));
+            }
+        }
+        
+        let columns = texture_info.width / TILE_SIZE;
+        let capacity = columns * (texture_info.height / TILE_SIZE);
+        if names.len() as u32 > capacity {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      format!("atlas layout names {} tiles, but the image \
+                                               only holds {}", names.len(), capacity)));
+        }
+        
+        let mut tiles = HashMap::new();
+        for info in registry.iter() {
+            for &face in &FACES {
+                if let Some(name) = info.texture(face) {
+                    let index = names.get(name).cloned().ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::InvalidData,
+                                       format!("atlas layout has no tile named {:?}", name))
+                    })?;
+                    
+                    tiles.insert((info.id(), face), index);
+                }
+            }
+        }
+        
+        Ok(Atlas {
+            tile_size: [TILE_SIZE as f32 / texture_info.width as f32,
+                        TILE_SIZE as f32 / texture_info.height as f32],
+            columns,
+            tiles,
+        })
+    }
+    
+    /// The size of one tile, in texture coordinates.
+    pub fn tile_size(&self) -> [f32; 2] {
+        self.tile_size
+    }
+    
+    /// The texture coordinates of the tile for one face of a block.
+    /// Blocks that are not drawn have no tile, so `None` is returned.
+    pub fn tile(&self, block: BlockId, face: Face) -> Option<[f32; 2]> {
+        self.tile_index(block, face).map(|index| {
+            [(index % self.columns) as f32 * self.tile_size[0],
+             (index / self.columns) as f32 * self.tile_size[1]]
+        })
+    }
+    
+    /// The index of the tile for one face of a block, counting
+    /// from left to right, then top to bottom. Blocks that are
+    /// not drawn have no tile, so `None` is returned.
+    pub fn tile_index(&self, block: BlockId, face: Face) -> Option<u32> {
+        self.tiles.get(&(block, face)).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use png::{BitDepth, ColorType};
+    use super::*;
+    
+    const BLOCKS: &str = "
+[air]
+id = 0
+
+[grass]
+id = 1
+texture = \"dirt\"
+top = \"grass\"
+";
+
+    // The layout of an image of 2 by 2 tiles.
+    fn load(layout: &str) -> io::Result<Atlas> {
+        let info = OutputInfo {
+            width: 2 * TILE_SIZE,
+            height: 2 * TILE_SIZE,
+            color_type: ColorType::RGBA,
+            bit_depth: BitDepth::Eight,
+            line_size: 2 * TILE_SIZE as usize * 4,
+        };
+        let registry = BlockRegistry::load(BLOCKS.as_bytes()).unwrap();
+        
+        Atlas::load(layout.as_bytes(), &info, &registry)
+    }
+    
+    fn error(layout: &str) -> String {
+        match load(layout) {
+            Ok(_) => panic!("the atlas should not load"),
+            Err(e) => e.to_string(),
+        }
+    }
+    
+    #[test]
+    fn tiles() {
+        let atlas = load("# A comment.\nstone\n\ndirt\ngrass\n").unwrap();
+        let grass = BlockId::new(1);
+        
+        assert_eq!(atlas.tile_size(), [0.5, 0.5]);
+        assert_eq!(atlas.tile_index(grass, Face::Top), Some(2));
+        assert_eq!(atlas.tile_index(grass, Face::Left), Some(1));
+        assert_eq!(atlas.tile(grass, Face::Top), Some([0., 0.5]));
+        assert_eq!(atlas.tile(grass, Face::Left), Some([0.5, 0.]));
+        assert_eq!(atlas.tile(BlockId::AIR, Face::Top), None);
+    }
+    
+    #[test]
+    fn unknown_tile() {
+        assert_eq!(error("dirt\n"), "atlas layout has no tile named \"grass\"");
+    }
+    
+    #[test]
+    fn duplicate_tile() {
+        assert_eq!(error("dirt\ngrass\ndirt\n"), "atlas layout names \"dirt\" twice");
+    }
+    
+    #[test]
+    fn too_many_tiles() {
+        assert_eq!(error("dirt\ngrass\nstone\nsand\nsnow\n"),
+                   "atlas layout names 5 tiles, but the image only holds 4");
+    }
+}
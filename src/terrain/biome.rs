@@ -0,0 +1,79 @@
+//! The biomes of the world, picked by climate.
+//!
+//! Every column of blocks has a temperature and a humidity, which
+//! vary slowly across the world. The climate decides how much each
+//! biome weighs in the column. Near the border between two biomes
+//! both weigh in, so that the shape of the ground changes gradually.
+
+/// A kind of region of the world, with its own
+/// shape of the ground, surface and trees.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Biome {
+    /// Gentle grassland with few trees.
+    Plains,
+    
+    /// Rolling grassland covered in trees.
+    Forest,
+    
+    /// Hot, dry, flat sand.
+    Desert,
+    
+    /// High, steep, bare rock.
+    Mountains,
+    
+    /// Cold hills covered in snow.
+    Tundra,
+}
+
+/// Every biome, in the order of the weights from `weights`.
+pub const BIOMES: [Biome; 5] = [
+    Biome::Plains,
+    Biome::Forest,
+    Biome::Desert,
+    Biome::Mountains,
+    Biome::Tundra,
+];
+
+// How far on either side of a border in the climate
+// the two biomes are blended.
+const BLEND: f32 = 0.08;
+
+/// How much each biome in `BIOMES` weighs in a column with this
+/// climate. Both `temperature` and `humidity` are from -1 to 1.
+/// The weights add up to 1.
+pub fn weights(temperature: f32, humidity: f32) -> [f32; 5] {
+    let warm = step(temperature, -0.35);
+    let hot = step(temperature, 0.35);
+    let wet = step(humidity, 0.25);
+    let damp = step(humidity, 0.);
+    let dry = 1. - step(humidity, -0.35);
+    
+    let desert = warm * hot * (1. - damp);
+    let temperate = warm - desert;
+    
+    [temperate * (1. - wet) * (1. - dry),
+     temperate * wet,
+     desert,
+     temperate * (1. - wet) * dry,
+     1. - warm]
+}
+
+/// The biome that weighs the most.
+pub fn dominant(weights: &[f32; 5]) -> Biome {
+    let mut best = 0;
+    
+    for (i, &weight) in weights.iter().enumerate() {
+        if weight > weights[best] {
+            best = i;
+        }
+    }
+    
+    BIOMES[best]
+}
+
+// Go smoothly from 0 to 1 as `value` passes `edge`.
+fn step(value: f32, edge: f32) -> f32 {
+    let t = ((value - edge) / (2. * BLEND) + 0.5).clamp(0., 1.);
+    
+    t * t * (3. - 2. * t)
+}
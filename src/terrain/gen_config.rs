@@ -0,0 +1,554 @@
+//! The settings of the noise based world generator,
+//! which may be loaded from a TOML file.
+//!
+//! Every section and setting of the file may be left out,
+//! in which case its default is used. The defaults are
+//! those of `WorldGenConfig::default`.
+
+use std::io::{self, Read};
+use toml;
+use super::SECTOR_SIZE;
+use super::biome::Biome;
+use super::registry::BlockRegistry;
+
+/// The settings of the noise based world generator.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorldGenConfig {
+    /// The shape of the ground.
+    pub terrain: TerrainConfig,
+    
+    /// The climate, which decides the biomes.
+    pub climate: ClimateConfig,
+    
+    /// The settings of each biome.
+    pub biomes: BiomesConfig,
+    
+    /// Where trees grow.
+    pub trees: TreeConfig,
+    
+    /// How caves are carved out of the ground.
+    pub caves: CaveConfig,
+    
+    /// The sea, which fills the low ground.
+    pub water: WaterConfig,
+    
+    /// The ores placed in the rock.
+    pub ores: Vec<OreConfig>,
+}
+
+impl Default for WorldGenConfig {
+    fn default() -> WorldGenConfig {
+        let ore = |block: &str, min_height, max_height, veins_per_sector, vein_size| OreConfig {
+            block: block.to_string(),
+            min_height,
+            max_height,
+            veins_per_sector,
+            vein_size,
+        };
+        
+        WorldGenConfig {
+            terrain: TerrainConfig::default(),
+            climate: ClimateConfig::default(),
+            biomes: BiomesConfig::default(),
+            trees: TreeConfig::default(),
+            caves: CaveConfig::default(),
+            water: WaterConfig::default(),
+            ores: vec![ore("coal_ore", -256, 128, 8., 14),
+                       ore("copper_ore", -192, 48, 5., 10),
+                       ore("iron_ore", -256, 16, 4., 8)],
+        }
+    }
+}
+
+impl WorldGenConfig {
+    /// Read the settings from a TOML file. The ores
+    /// must be blocks of `registry`.
+    /// # Errors
+    /// Fails if the file cannot be read, if it is not valid
+    /// TOML, if it has settings that do not exist or that are
+    /// of the wrong type, if a setting is out of range, or if
+    /// an ore is not a block of the registry.
+    pub fn load<R: Read>(mut data: R, registry: &BlockRegistry) -> io::Result<WorldGenConfig> {
+        let mut text = String::new();
+        data.read_to_string(&mut text)?;
+        
+        let config: WorldGenConfig = toml::from_str(&text).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+        })?;
+        
+        config.validate(registry).map_err(|msg| {
+            io::Error::new(io::ErrorKind::InvalidData, msg)
+        })?;
+        
+        Ok(config)
+    }
+    
+    // Check that every setting is in range, and
+    // that the ores are blocks of the registry.
+    fn validate(&self, registry: &BlockRegistry) -> Result<(), String> {
+        let terrain = &self.terrain;
+        positive("terrain.base_persistence", terrain.base_persistence)?;
+        positive("terrain.base_scale", terrain.base_scale)?;
+        positive("terrain.compression_persistence", terrain.compression_persistence)?;
+        positive("terrain.compression_scale", terrain.compression_scale)?;
+        finite("terrain.compression_offset", terrain.compression_offset)?;
+        octaves("terrain.general_octaves", terrain.general_octaves)?;
+        positive("terrain.general_frequency", terrain.general_frequency)?;
+        positive("terrain.general_scale", terrain.general_scale)?;
+        finite("terrain.general_offset", terrain.general_offset)?;
+        
+        octaves("climate.octaves", self.climate.octaves)?;
+        positive("climate.size", self.climate.size)?;
+        positive("climate.contrast", self.climate.contrast)?;
+        
+        for &(name, biome) in &BIOME_NAMES {
+            let config = self.biomes.get(biome);
+            finite(&format!("biomes.{}.base_height", name), config.base_height)?;
+            finite(&format!("biomes.{}.height_scale", name), config.height_scale)?;
+            fraction(&format!("biomes.{}.tree_density", name), config.tree_density)?;
+        }
+        
+        positive("trees.grove_frequency", self.trees.grove_frequency)?;
+        positive("trees.grove_scale", self.trees.grove_scale)?;
+        non_negative("trees.grove_growth", self.trees.grove_growth)?;
+        positive("trees.spacing_frequency", self.trees.spacing_frequency)?;
+        positive("trees.spacing_scale", self.trees.spacing_scale)?;
+        finite("trees.spacing_threshold", self.trees.spacing_threshold)?;
+        
+        fraction("caves.density", self.caves.density)?;
+        if self.caves.min_depth < 0 {
+            return Err(format!("caves.min_depth must not be negative, but is {}",
+                               self.caves.min_depth));
+        }
+        non_negative("caves.width", self.caves.width)?;
+        positive("caves.tunnel_size", self.caves.tunnel_size)?;
+        positive("caves.cavern_size", self.caves.cavern_size)?;
+        non_negative("caves.cavern_spread", self.caves.cavern_spread)?;
+        non_negative("caves.tunnel_spread", self.caves.tunnel_spread)?;
+        
+        if self.water.beach_height < 0 {
+            return Err(format!("water.beach_height must not be negative, but is {}",
+                               self.water.beach_height));
+        }
+        
+        for ore in &self.ores {
+            let name = format!("ores.{}", ore.block);
+            if registry.id(&ore.block).is_none() {
+                return Err(format!("{}: there is no block named {:?}", name, ore.block));
+            }
+            
+            if ore.min_height > ore.max_height {
+                return Err(format!("{}.min_height must not be above max_height, but {} > {}",
+                                   name, ore.min_height, ore.max_height));
+            }
+            
+            non_negative(&format!("{}.veins_per_sector", name), ore.veins_per_sector)?;
+            if ore.vein_size < 1 || ore.vein_size > SECTOR_SIZE as u32 {
+                return Err(format!("{}.vein_size must be from 1 to {}, but is {}",
+                                   name, SECTOR_SIZE, ore.vein_size));
+            }
+        }
+        
+        Ok(())
+    }
+}
+
+/// The shape of the ground, before the biomes raise or lower it.
+///
+/// Hills come from the base noise. The compression noise spreads
+/// them apart or pushes them together, and the general noise
+/// flattens whole regions.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TerrainConfig {
+    /// The persistence of the base noise.
+    pub base_persistence: f32,
+    
+    /// How much the base noise changes from one block to the next.
+    pub base_scale: f32,
+    
+    /// The persistence of the compression noise.
+    pub compression_persistence: f32,
+    
+    /// How much the compression noise changes
+    /// from one block to the next.
+    pub compression_scale: f32,
+    
+    /// Added to the compression noise, which is then
+    /// at most 1. The higher it is, the less the hills
+    /// are spread apart.
+    pub compression_offset: f32,
+    
+    /// The number of octaves of the general noise.
+    pub general_octaves: usize,
+    
+    /// The frequency of the general noise.
+    pub general_frequency: f32,
+    
+    /// How much the general noise changes
+    /// from one block to the next.
+    pub general_scale: f32,
+    
+    /// Added to the general noise, which is then at most 1.
+    /// The higher it is, the fewer regions are flattened.
+    pub general_offset: f32,
+}
+
+impl Default for TerrainConfig {
+    fn default() -> TerrainConfig {
+        TerrainConfig {
+            base_persistence: 0.1,
+            base_scale: 0.007,
+            compression_persistence: 0.05,
+            compression_scale: 0.005,
+            compression_offset: 1.,
+            general_octaves: 4,
+            general_frequency: 0.5,
+            general_scale: 0.0009,
+            general_offset: 1.5,
+        }
+    }
+}
+
+/// The climate, which decides the biome of every column.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClimateConfig {
+    /// The number of octaves of the temperature
+    /// and humidity noise.
+    pub octaves: usize,
+    
+    /// The rough size of a biome, in blocks.
+    pub size: f32,
+    
+    /// How much the climate noise is stretched. The noise rarely
+    /// strays far from zero, so without stretching, the extreme
+    /// biomes would be rare.
+    pub contrast: f32,
+}
+
+impl Default for ClimateConfig {
+    fn default() -> ClimateConfig {
+        ClimateConfig {
+            octaves: 3,
+            size: 800.,
+            contrast: 2.,
+        }
+    }
+}
+
+/// The settings of every biome. Each biome that is given
+/// in a file must have all of its settings.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BiomesConfig {
+    /// The settings of `Biome::Plains`.
+    pub plains: BiomeConfig,
+    
+    /// The settings of `Biome::Forest`.
+    pub forest: BiomeConfig,
+    
+    /// The settings of `Biome::Desert`.
+    pub desert: BiomeConfig,
+    
+    /// The settings of `Biome::Mountains`.
+    pub mountains: BiomeConfig,
+    
+    /// The settings of `Biome::Tundra`.
+    pub tundra: BiomeConfig,
+}
+
+impl BiomesConfig {
+    /// The settings of one biome.
+    pub fn get(&self, biome: Biome) -> &BiomeConfig {
+        match biome {
+            Biome::Plains => &self.plains,
+            Biome::Forest => &self.forest,
+            Biome::Desert => &self.desert,
+            Biome::Mountains => &self.mountains,
+            Biome::Tundra => &self.tundra,
+        }
+    }
+}
+
+impl Default for BiomesConfig {
+    fn default() -> BiomesConfig {
+        let biome = |base_height, height_scale, tree_density| BiomeConfig {
+            base_height,
+            height_scale,
+            tree_density,
+        };
+        
+        BiomesConfig {
+            plains: biome(16., 32., 0.3),
+            forest: biome(20., 48., 1.),
+            desert: biome(12., 24., 0.),
+            mountains: biome(48., 160., 0.15),
+            tundra: biome(24., 56., 0.2),
+        }
+    }
+}
+
+/// The settings of one biome.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BiomeConfig {
+    /// The height of the ground where the terrain noise is zero.
+    pub base_height: f32,
+    
+    /// How far the terrain noise raises or lowers the ground.
+    pub height_scale: f32,
+    
+    /// How densely trees grow, from 0 for none
+    /// at all to 1 for a thick forest.
+    pub tree_density: f32,
+}
+
+/// Where trees grow.
+///
+/// Trees grow in groves, where the grove noise is high enough
+/// for the tree density of the biome. Within a grove, a tree
+/// grows wherever the spacing noise is above a threshold.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TreeConfig {
+    /// The frequency of the grove noise.
+    pub grove_frequency: f32,
+    
+    /// How much the grove noise changes from one block to the next.
+    pub grove_scale: f32,
+    
+    /// How quickly groves grow with the tree density. The
+    /// grove noise must be above 1 minus this times the density.
+    pub grove_growth: f32,
+    
+    /// The frequency of the spacing noise.
+    pub spacing_frequency: f32,
+    
+    /// How much the spacing noise changes from one block to the next.
+    pub spacing_scale: f32,
+    
+    /// The spacing noise must be above this for a tree to grow.
+    pub spacing_threshold: f32,
+}
+
+impl Default for TreeConfig {
+    fn default() -> TreeConfig {
+        TreeConfig {
+            grove_frequency: 0.01,
+            grove_scale: 1.1,
+            grove_growth: 1.6,
+            spacing_frequency: 1.0,
+            spacing_scale: 0.55,
+            spacing_threshold: 0.25,
+        }
+    }
+}
+
+/// How caves are carved out of the ground.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CaveConfig {
+    /// Roughly how much of the ground is hollowed out by large
+    /// caverns, from 0 for none to 1 for all of it.
+    pub density: f32,
+    
+    /// How many blocks below the surface caves begin.
+    pub min_depth: i32,
+    
+    /// The rough width of the tunnels that wind between the
+    /// caverns, in blocks. There are no tunnels if this is 0.
+    pub width: f32,
+    
+    /// The rough distance between tunnels, in blocks.
+    pub tunnel_size: f32,
+    
+    /// The rough size of a cavern, in blocks.
+    pub cavern_size: f32,
+    
+    /// Matches the cavern noise to the density. Caverns are
+    /// where the noise is above 1 minus this times the square
+    /// root of the density.
+    pub cavern_spread: f32,
+    
+    /// Matches the tunnel noise to the width. Tunnels are where
+    /// the noise is within this times the width divided by the
+    /// tunnel size of zero.
+    pub tunnel_spread: f32,
+}
+
+impl Default for CaveConfig {
+    fn default() -> CaveConfig {
+        CaveConfig {
+            density: 0.04,
+            min_depth: 8,
+            width: 4.,
+            tunnel_size: 64.,
+            cavern_size: 48.,
+            cavern_spread: 1.5,
+            tunnel_spread: 0.55,
+        }
+    }
+}
+
+/// The sea, which fills the ground below the sea level with water.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WaterConfig {
+    /// The height of the lowest air above the water. Ground
+    /// that is lower than this lies under water.
+    pub sea_level: i32,
+    
+    /// How far above or below the sea level the ground may be
+    /// for it to be covered in sand. Deeper ground under water
+    /// is bare soil, and trees only grow above the beaches.
+    pub beach_height: i32,
+}
+
+impl Default for WaterConfig {
+    fn default() -> WaterConfig {
+        WaterConfig {
+            sea_level: 8,
+            beach_height: 2,
+        }
+    }
+}
+
+/// An ore, which is placed in veins that wind through the rock.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OreConfig {
+    /// The name of the ore block.
+    pub block: String,
+    
+    /// The lowest height at which a vein starts.
+    pub min_height: i32,
+    
+    /// The highest height at which a vein starts.
+    pub max_height: i32,
+    
+    /// The average number of veins in a sector
+    /// that lies between the two heights.
+    pub veins_per_sector: f32,
+    
+    /// The most blocks in a vein, which is at most the size
+    /// of a sector. Veins are often smaller, where they turn
+    /// back on themselves or leave the rock.
+    pub vein_size: u32,
+}
+
+// The name of each biome in the file.
+const BIOME_NAMES: [(&str, Biome); 5] = [
+    ("plains", Biome::Plains),
+    ("forest", Biome::Forest),
+    ("desert", Biome::Desert),
+    ("mountains", Biome::Mountains),
+    ("tundra", Biome::Tundra),
+];
+
+fn finite(name: &str, value: f32) -> Result<(), String> {
+    if value.is_finite() {
+        Ok(())
+    } else {
+        Err(format!("{} must be a finite number, but is {}", name, value))
+    }
+}
+
+fn positive(name: &str, value: f32) -> Result<(), String> {
+    if value > 0. && value.is_finite() {
+        Ok(())
+    } else {
+        Err(format!("{} must be greater than 0, but is {}", name, value))
+    }
+}
+
+fn non_negative(name: &str, value: f32) -> Result<(), String> {
+    if value >= 0. && value.is_finite() {
+        Ok(())
+    } else {
+        Err(format!("{} must not be negative, but is {}", name, value))
+    }
+}
+
+fn fraction(name: &str, value: f32) -> Result<(), String> {
+    if (0. ..=1.).contains(&value) {
+        Ok(())
+    } else {
+        Err(format!("{} must be from 0 to 1, but is {}", name, value))
+    }
+}
+
+// The noise library allows at most 32 octaves.
+fn octaves(name: &str, value: usize) -> Result<(), String> {
+    if (1..=32).contains(&value) {
+        Ok(())
+    } else {
+        Err(format!("{} must be from 1 to 32, but is {}", name, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use super::*;
+    
+    fn registry() -> BlockRegistry {
+        BlockRegistry::load(File::open("data/blocks.toml").unwrap()).unwrap()
+    }
+    
+    // The error of loading a file, which must fail.
+    fn error(text: &str) -> String {
+        WorldGenConfig::load(text.as_bytes(), &registry()).unwrap_err().to_string()
+    }
+    
+    #[test]
+    fn default_file_matches_defaults() {
+        let registry = registry();
+        let file = WorldGenConfig::load(File::open("data/worldgen.toml").unwrap(),
+                                        &registry).unwrap();
+        let default = WorldGenConfig::default();
+        
+        assert_eq!(format!("{:?}", file), format!("{:?}", default));
+        
+        // The settings that were once constants keep their values,
+        // so that worlds are generated as they were before.
+        assert_eq!(default.terrain.compression_offset, 1.0);
+        assert_eq!(default.terrain.general_offset, 1.5);
+        assert_eq!(default.trees.grove_growth, 1.6);
+        assert_eq!(default.caves.cavern_spread, 1.5);
+        assert_eq!(default.caves.tunnel_spread, 0.55);
+    }
+    
+    #[test]
+    fn wrong_type() {
+        assert!(error("[caves]\nwidth = \"wide\"\n").starts_with("invalid type: string"));
+    }
+    
+    #[test]
+    fn unknown_setting() {
+        assert!(error("[caves]\nwidht = 4.0\n").starts_with("unknown field `widht`"));
+    }
+    
+    #[test]
+    fn out_of_range() {
+        assert_eq!(error("[trees]\ngrove_growth = -1.0\n"),
+                   "trees.grove_growth must not be negative, but is -1");
+        assert_eq!(error("[terrain]\ngeneral_offset = nan\n"),
+                   "terrain.general_offset must be a finite number, but is NaN");
+        assert_eq!(error("[caves]\ntunnel_spread = inf\n"),
+                   "caves.tunnel_spread must not be negative, but is inf");
+    }
+    
+    #[test]
+    fn unknown_ore_block() {
+        let text = "[[ores]]\n\
+                    block = \"cole_ore\"\n\
+                    min_height = -64\n\
+                    max_height = 0\n\
+                    veins_per_sector = 1.0\n\
+                    vein_size = 4\n";
+        
+        assert_eq!(error(text), "ores.cole_ore: there is no block named \"cole_ore\"");
+    }
+}
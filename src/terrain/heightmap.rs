@@ -0,0 +1,538 @@
+//! Terrain built from a heightmap, such as a hand-painted
+//! grayscale image or an elevation model of the real world.
+//!
+//! Heightmaps may be read from 8 or 16 bit grayscale PNG images,
+//! or from ESRI ASCII grids. The heightmap is centered on the
+//! origin, with its first row toward negative z, and samples
+//! are blended smoothly between the columns they cover.
+
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::str::FromStr;
+use png::{BitDepth, ColorType, Decoder, Transformations};
+use super::SECTOR_SIZE;
+use super::registry::BlockRegistry;
+use super::voxel::{BlockId, BlockList, SectorSpaceCoords};
+use super::world_gen::WorldGenerator;
+
+/// A grid of height samples.
+#[derive(Clone, Debug)]
+pub struct Heightmap {
+    width: u32,
+    depth: u32,
+    samples: Vec<f32>,
+}
+
+impl Heightmap {
+    /// Create a `Heightmap` from its samples, listed row by row.
+    /// # Panics
+    /// Panics if there is not one sample for every cell,
+    /// or if there are no samples.
+    pub fn new(width: u32, depth: u32, samples: Vec<f32>) -> Heightmap {
+        assert_eq!(samples.len(), width as usize * depth as usize,
+                   "a heightmap needs one sample for every cell");
+        assert!(!samples.is_empty(), "a heightmap needs at least one sample");
+        
+        Heightmap {
+            width,
+            depth,
+            samples,
+        }
+    }
+    
+    /// Read a heightmap from a file, which is a PNG
+    /// image if it ends in `.png` or an ESRI ASCII
+    /// grid if it ends in `.asc`.
+    /// # Errors
+    /// Fails if the file cannot be read, if it has any
+    /// other extension, or if it is not a valid heightmap.
+    pub fn open(path: &Path) -> io::Result<Heightmap> {
+        match path.extension().and_then(OsStr::to_str) {
+            Some("png") => Heightmap::load_png(File::open(path)?),
+            Some("asc") => Heightmap::load_ascii_grid(File::open(path)?),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                    "heightmaps must end in .png or .asc")),
+        }
+    }
+    
+    /// Read a heightmap from a grayscale PNG image. Each pixel is
+    /// one sample, from 0 for black to 1 for white. 16 bit images
+    /// are read at their full precision. Transparency is ignored.
+    /// # Errors
+    /// Fails if the image cannot be read, or if it is not grayscale.
+    pub fn load_png<R: Read>(data: R) -> io::Result<Heightmap> {
+        // Expand images of less than 8 bits to 8 bits, and a
+        // transparent gray to an alpha channel. 16 bit images
+        // are not narrowed, so they keep their precision.
+        let mut decoder = Decoder::new(data);
+        decoder.set_transformations(Transformations::EXPAND);
+        
+        let (info, mut reader) = decoder.read_info().map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+        })?;
+        
+        // Images with a transparent gray are expanded
+        // to gray and alpha, and the alpha is skipped.
+        let channels = match info.color_type {
+            ColorType::Grayscale => 1,
+            ColorType::GrayscaleAlpha => 2,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                           "heightmap images must be grayscale")),
+        };
+        
+        let sixteen = info.bit_depth == BitDepth::Sixteen;
+        let stride = if sixteen { 2 * channels } else { channels };
+        
+        let mut pixels = vec![0; info.buffer_size()];
+        reader.next_frame(&mut pixels).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+        })?;
+        
+        let mut samples = Vec::with_capacity(info.width as usize * info.height as usize);
+        
+        for row in pixels.chunks(info.line_size).take(info.height as usize) {
+            for pixel in row.chunks(stride).take(info.width as usize) {
+                samples.push(if sixteen {
+                    (pixel[0] as u32 * 256 + pixel[1] as u32) as f32 / 65535.
+                } else {
+                    pixel[0] as f32 / 255.
+                });
+            }
+        }
+        
+        Ok(Heightmap::new(info.width, info.height, samples))
+    }
+    
+    /// Read a heightmap from an ESRI ASCII grid. Each cell is one
+    /// sample, which is the elevation given in the file. Cells
+    /// without data are as low as the lowest cell with data. The
+    /// size of the cells in the header is not used, as it may be in
+    /// degrees rather than meters. `HeightmapOptions` scales them.
+    /// # Errors
+    /// Fails if the grid cannot be read, if its header lacks
+    /// `ncols` or `nrows`, or if it has the wrong number of cells.
+    pub fn load_ascii_grid<R: Read>(data: R) -> io::Result<Heightmap> {
+        let mut columns = None;
+        let mut rows = None;
+        let mut no_data = None;
+        let mut samples = Vec::new();
+        
+        for (number, line) in BufReader::new(data).lines().enumerate() {
+            let line = line?;
+            let number = number + 1;
+            let mut words = line.split_whitespace().peekable();
+            
+            // The header has one `key value` pair on each line,
+            // and the cells begin at the first line of numbers.
+            let is_header = samples.is_empty() && words.peek().is_some_and(|word| {
+                word.starts_with(|c: char| c.is_ascii_alphabetic())
+            });
+            
+            if is_header {
+                let key = words.next().unwrap().to_ascii_lowercase();
+                let value = words.next().ok_or_else(|| {
+                    invalid_data(number, &format!("{} has no value", key))
+                })?;
+                
+                match &key[..] {
+                    "ncols" => columns = Some(parse_count(number, &key, value)?),
+                    "nrows" => rows = Some(parse_count(number, &key, value)?),
+                    "nodata_value" => no_data = Some(parse_number(number, value)?),
+                    // The position and size of the cells
+                    // are replaced by the scale options.
+                    "xllcorner" | "yllcorner" | "xllcenter" | "yllcenter" | "cellsize" => {},
+                    _ => return Err(invalid_data(number, &format!("unknown header {:?}", key))),
+                }
+                
+                continue;
+            }
+            
+            for word in words {
+                samples.push(parse_number(number, word)?);
+            }
+        }
+        
+        let (columns, rows) = match (columns, rows) {
+            (Some(columns), Some(rows)) => (columns, rows),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                           "the grid needs both ncols and nrows")),
+        };
+        
+        if samples.len() != columns as usize * rows as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      format!("the grid should have {} cells, but has {}",
+                                              columns as usize * rows as usize,
+                                              samples.len())));
+        }
+        
+        if let Some(no_data) = no_data {
+            let lowest = samples.iter().cloned()
+                                .filter(|&sample| sample != no_data)
+                                .fold(None, |lowest: Option<f32>, sample| {
+                                    Some(lowest.map_or(sample, |l| l.min(sample)))
+                                });
+            
+            let lowest = lowest.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "the grid has no cells with data")
+            })?;
+            
+            for sample in &mut samples {
+                if *sample == no_data {
+                    *sample = lowest;
+                }
+            }
+        }
+        
+        Ok(Heightmap::new(columns, rows, samples))
+    }
+    
+    /// The number of samples along the x axis.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    
+    /// The number of samples along the z axis.
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+    
+    /// The sample at a position in the grid, which
+    /// is found beyond its edges by `edges`.
+    pub fn get(&self, x: i32, z: i32, edges: Edges) -> f32 {
+        let (x, z) = match edges {
+            Edges::Clamp => (x.max(0).min(self.width as i32 - 1),
+                             z.max(0).min(self.depth as i32 - 1)),
+            Edges::Tile => (wrap(x, self.width as i32), wrap(z, self.depth as i32)),
+        };
+        
+        self.samples[z as usize * self.width as usize + x as usize]
+    }
+}
+
+/// What lies beyond the edges of a heightmap.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Edges {
+    /// The samples along each edge go on forever.
+    Clamp,
+    
+    /// The heightmap repeats in every direction.
+    Tile,
+}
+
+impl FromStr for Edges {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Edges, String> {
+        match s {
+            "clamp" => Ok(Edges::Clamp),
+            "tile" => Ok(Edges::Tile),
+            _ => Err(format!("unknown edges {:?}, which must be clamp or tile", s)),
+        }
+    }
+}
+
+/// How a heightmap is turned into terrain.
+#[derive(Clone, Debug)]
+pub struct HeightmapOptions {
+    /// The width of one sample, in blocks.
+    pub horizontal_scale: f32,
+    
+    /// The height of one unit of a sample, in blocks.
+    pub vertical_scale: f32,
+    
+    /// The height of the ground where a sample is 0.
+    pub base_height: f32,
+    
+    /// What lies beyond the edges of the heightmap.
+    pub edges: Edges,
+    
+    /// The block on top of the ground, by the height of the ground.
+    /// Each entry holds the lowest height at which its block is
+    /// used, and they are listed from the lowest up. Ground lower
+    /// than every entry is topped with `subsurface`.
+    pub surface: Vec<(i32, BlockId)>,
+    
+    /// The block beneath the surface.
+    pub subsurface: BlockId,
+    
+    /// How deep the surface and the blocks beneath it go.
+    pub soil_depth: i32,
+    
+    /// The block beneath the soil.
+    pub rock: BlockId,
+}
+
+impl HeightmapOptions {
+    /// Create options that place every sample at its own column,
+    /// and a sample of 1 at a height of 64 blocks. The ground is
+    /// grass on loam on limestone, and clamped at the edges.
+    /// # Panics
+    /// Panics if `registry` lacks one of these blocks.
+    pub fn new(registry: &BlockRegistry) -> HeightmapOptions {
+        let block = |name| registry.id(name).unwrap_or_else(|| {
+            panic!("heightmap worlds need a block named {:?}", name)
+        });
+        
+        HeightmapOptions {
+            horizontal_scale: 1.,
+            vertical_scale: 64.,
+            base_height: 0.,
+            edges: Edges::Clamp,
+            surface: vec![(i32::MIN, block("grass"))],
+            subsurface: block("loam"),
+            soil_depth: 4,
+            rock: block("limestone"),
+        }
+    }
+    
+    // The block on top of ground of this height.
+    fn surface_block(&self, height: i32) -> BlockId {
+        self.surface.iter()
+                    .take_while(|&&(lowest, _)| lowest <= height)
+                    .last()
+                    .map_or(self.subsurface, |&(_, block)| block)
+    }
+}
+
+/// A world whose ground follows a heightmap.
+#[derive(Clone)]
+pub struct HeightmapWorld {
+    map: Heightmap,
+    options: HeightmapOptions,
+}
+
+impl HeightmapWorld {
+    /// Create a `HeightmapWorld` from a heightmap.
+    /// # Panics
+    /// Panics if the horizontal scale is not greater than 0.
+    pub fn new(map: Heightmap, options: HeightmapOptions) -> HeightmapWorld {
+        assert!(options.horizontal_scale > 0., "the horizontal scale must be greater than 0");
+        
+        HeightmapWorld {
+            map,
+            options,
+        }
+    }
+}
+
+impl WorldGenerator for HeightmapWorld {
+    fn generate(&self, sector: (i32, i32, i32)) -> BlockList {
+        let size = SECTOR_SIZE as i32;
+        let bottom = sector.1 * size;
+        let mut heights = [[0; SECTOR_SIZE]; SECTOR_SIZE];
+        let mut lowest = i32::MAX;
+        let mut highest = i32::MIN;
+        
+        for (z, row) in heights.iter_mut().enumerate() {
+            for (x, height) in row.iter_mut().enumerate() {
+                *height = self.height_at(sector.0 * size + x as i32, sector.2 * size + z as i32);
+                
+                lowest = lowest.min(*height);
+                highest = highest.max(*height);
+            }
+        }
+        
+        // Sectors entirely above or below the surface
+        // are the same everywhere.
+        if bottom >= highest {
+            return BlockList::new_air();
+        }
+        
+        if bottom + size <= lowest - self.options.soil_depth {
+            return BlockList::filled(self.options.rock);
+        }
+        
+        let mut list = BlockList::new_air();
+        
+        for (z, row) in heights.iter().enumerate() {
+            for (x, &height) in row.iter().enumerate() {
+                let surface = self.options.surface_block(height);
+                
+                for y in 0..SECTOR_SIZE {
+                    let world_y = bottom + y as i32;
+                    
+                    let block = if world_y >= height {
+                        break;
+                    } else if world_y == height - 1 {
+                        surface
+                    } else if world_y >= height - self.options.soil_depth {
+                        self.options.subsurface
+                    } else {
+                        self.options.rock
+                    };
+                    
+                    list.set(SectorSpaceCoords::new(x as u8, y as u8, z as u8), block);
+                }
+            }
+        }
+        
+        list
+    }
+    
+    fn height_at(&self, x: i32, z: i32) -> i32 {
+        // The position in the grid of the center of the column,
+        // with the center of the grid at the origin.
+        let scale = self.options.horizontal_scale;
+        let grid_x = (x as f32 + 0.5) / scale + self.map.width as f32 / 2. - 0.5;
+        let grid_z = (z as f32 + 0.5) / scale + self.map.depth as f32 / 2. - 0.5;
+        
+        let (x0, z0) = (grid_x.floor(), grid_z.floor());
+        let (tx, tz) = (grid_x - x0, grid_z - z0);
+        let (x0, z0) = (x0 as i32, z0 as i32);
+        
+        let edges = self.options.edges;
+        let sample = |x, z| self.map.get(x, z, edges);
+        let near = sample(x0, z0) * (1. - tx) + sample(x0 + 1, z0) * tx;
+        let far = sample(x0, z0 + 1) * (1. - tx) + sample(x0 + 1, z0 + 1) * tx;
+        let value = near * (1. - tz) + far * tz;
+        
+        (self.options.base_height + value * self.options.vertical_scale).round() as i32
+    }
+}
+
+// Wrap a position in the grid around to the range 0 to `len`.
+fn wrap(pos: i32, len: i32) -> i32 {
+    (pos % len + len) % len
+}
+
+fn parse_count(line: usize, key: &str, value: &str) -> io::Result<u32> {
+    match value.parse() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(invalid_data(line, &format!("{} must be a positive integer", key))),
+    }
+}
+
+fn parse_number(line: usize, value: &str) -> io::Result<f32> {
+    value.parse().map_err(|_| invalid_data(line, &format!("{:?} is not a number", value)))
+}
+
+fn invalid_data(line: usize, msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", line, msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use png::{chunk, Encoder};
+    use super::*;
+    
+    // Encode a PNG image in memory, with an optional tRNS chunk.
+    fn png(width: u32, height: u32, color: ColorType, depth: BitDepth,
+           transparent: Option<&[u8]>, data: &[u8]) -> Vec<u8> {
+        let mut image = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut image, width, height);
+            encoder.set_color(color);
+            encoder.set_depth(depth);
+            
+            let mut writer = encoder.write_header().unwrap();
+            if let Some(transparent) = transparent {
+                writer.write_chunk(chunk::tRNS, transparent).unwrap();
+            }
+            
+            writer.write_image_data(data).unwrap();
+        }
+        
+        image
+    }
+    
+    fn samples(heightmap: &Heightmap) -> Vec<f32> {
+        let mut samples = Vec::new();
+        for z in 0..heightmap.depth() as i32 {
+            for x in 0..heightmap.width() as i32 {
+                samples.push(heightmap.get(x, z, Edges::Clamp));
+            }
+        }
+        
+        samples
+    }
+    
+    fn error(result: io::Result<Heightmap>) -> String {
+        result.expect_err("the heightmap should not load").to_string()
+    }
+    
+    #[test]
+    fn png_8_bit() {
+        let image = png(2, 2, ColorType::Grayscale, BitDepth::Eight, None, &[0, 255, 51, 102]);
+        let heightmap = Heightmap::load_png(&image[..]).unwrap();
+        
+        assert_eq!((heightmap.width(), heightmap.depth()), (2, 2));
+        assert_eq!(samples(&heightmap), vec![0., 1., 0.2, 0.4]);
+    }
+    
+    #[test]
+    fn png_16_bit() {
+        let data = [0x00, 0x00, 0x12, 0x34, 0xff, 0xff];
+        let image = png(3, 1, ColorType::Grayscale, BitDepth::Sixteen, None, &data);
+        let heightmap = Heightmap::load_png(&image[..]).unwrap();
+        
+        assert_eq!(samples(&heightmap), vec![0., 0x1234 as f32 / 65535., 1.]);
+    }
+    
+    #[test]
+    fn png_with_alpha() {
+        let data = [10, 255, 200, 0];
+        let image = png(2, 1, ColorType::GrayscaleAlpha, BitDepth::Eight, None, &data);
+        let heightmap = Heightmap::load_png(&image[..]).unwrap();
+        
+        assert_eq!(samples(&heightmap), vec![10. / 255., 200. / 255.]);
+    }
+    
+    #[test]
+    fn png_with_transparent_gray() {
+        // A 16 bit gray of 0x1234 is transparent.
+        let data = [0x12, 0x34, 0xff, 0xff];
+        let image = png(2, 1, ColorType::Grayscale, BitDepth::Sixteen, Some(&[0x12, 0x34]),
+                        &data);
+        let heightmap = Heightmap::load_png(&image[..]).unwrap();
+        
+        assert_eq!(samples(&heightmap), vec![0x1234 as f32 / 65535., 1.]);
+        
+        // Four 2 bit pixels, in one byte, of which 1 is transparent.
+        let image = png(4, 1, ColorType::Grayscale, BitDepth::Two, Some(&[0, 1]),
+                        &[0b00_01_10_11]);
+        let heightmap = Heightmap::load_png(&image[..]).unwrap();
+        
+        assert_eq!(samples(&heightmap), vec![0., 85. / 255., 170. / 255., 1.]);
+    }
+    
+    #[test]
+    fn png_in_color() {
+        let image = png(1, 1, ColorType::RGB, BitDepth::Eight, None, &[1, 2, 3]);
+        
+        assert_eq!(error(Heightmap::load_png(&image[..])), "heightmap images must be grayscale");
+    }
+    
+    #[test]
+    fn ascii_grid() {
+        let grid = "ncols 3\n\
+                    NROWS 2\n\
+                    xllcorner 100.5\n\
+                    cellsize 0.01\n\
+                    nodata_value -9999\n\
+                    1.5 2 3\n\
+                    -9999 5\n\
+                    6\n";
+        let heightmap = Heightmap::load_ascii_grid(grid.as_bytes()).unwrap();
+        
+        assert_eq!((heightmap.width(), heightmap.depth()), (3, 2));
+        assert_eq!(samples(&heightmap), vec![1.5, 2., 3., 1.5, 5., 6.]);
+    }
+    
+    #[test]
+    fn ascii_grid_without_header() {
+        let grid = "1 2 3\n4 5 6\n";
+        
+        assert_eq!(error(Heightmap::load_ascii_grid(grid.as_bytes())),
+                   "the grid needs both ncols and nrows");
+    }
+    
+    #[test]
+    fn ascii_grid_with_wrong_size() {
+        let grid = "ncols 2\nnrows 2\n1 2 3\n";
+        
+        assert_eq!(error(Heightmap::load_ascii_grid(grid.as_bytes())),
+                   "the grid should have 4 cells, but has 3");
+    }
+}
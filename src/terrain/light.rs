@@ -0,0 +1,777 @@
+//! Block light and sky light, spread through the loaded
+//! sectors by flood fill.
+//!
+//! Every block holds two light levels from 0 to `MAX_LIGHT`.
+//! Block light starts at the blocks that emit it, and sky light
+//! starts at the top of the world. Light loses one level for every
+//! block it travels, except that full sky light travels straight
+//! down without fading. Only transparent blocks let light in.
+//!
+//! The light within a sector is found by the worker threads before
+//! the sector is loaded, from snapshots of the light of its neighbors.
+//! The main thread only spreads light across the sides of the sectors
+//! it loads, and around the blocks that change.
+//!
+//! Sky light only enters a sector from above when the sector above
+//! it is loaded, or when the sector is above the surface and nothing
+//! can be above it. Otherwise the sector stays dark until the sector
+//! above it is loaded and its light spreads down. Light is not updated
+//! when sectors are unloaded.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ptr;
+use std::sync::Arc;
+use super::{split_world_coords, SECTOR_SIZE};
+use super::mesh_gen::{Face, FACES};
+use super::registry::{BlockRegistry, MAX_LIGHT};
+use super::voxel::{BlockList, Sector, SectorSpaceCoords, SECTOR_LEN};
+
+/// A kind of light.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channel {
+    /// Light from the sky.
+    Sky,
+    
+    /// Light emitted by blocks.
+    Block,
+}
+
+/// Every kind of light.
+pub const CHANNELS: [Channel; 2] = [Channel::Sky, Channel::Block];
+
+/// The light levels of every block in a `Sector`.
+/// Most sectors are either solid rock or open air, so a sector in
+/// which every block has the same light stores just that light.
+/// It stores the light of each block once one of them changes.
+#[derive(Clone)]
+pub struct LightList(Levels);
+
+// The representation of a `LightList`. Each level holds the
+// sky light in bits 4 to 7 and the block light in bits 0 to 3.
+#[derive(Clone)]
+enum Levels {
+    Uniform(u8),
+    Dense(Vec<u8>),
+}
+
+impl LightList {
+    /// Create a `LightList` in which every block is dark.
+    pub fn dark() -> LightList {
+        LightList(Levels::Uniform(0))
+    }
+    
+    /// Look at the light of one channel at a position in sector coords.
+    pub fn get(&self, pos: SectorSpaceCoords, channel: Channel) -> u8 {
+        let packed = match self.0 {
+            Levels::Uniform(packed) => packed,
+            Levels::Dense(ref levels) => levels[pos.index()],
+        };
+        
+        match channel {
+            Channel::Sky => packed >> 4,
+            Channel::Block => packed & 0xf,
+        }
+    }
+    
+    /// Set the light of one channel at a position in sector coords.
+    pub fn set(&mut self, pos: SectorSpaceCoords, channel: Channel, level: u8) {
+        // A uniform list only stores the light of
+        // each block once it is no longer uniform.
+        if let Levels::Uniform(packed) = self.0 {
+            if with_level(packed, channel, level) == packed {
+                return;
+            }
+            
+            self.0 = Levels::Dense(vec![packed; SECTOR_LEN]);
+        }
+        
+        if let Levels::Dense(ref mut levels) = self.0 {
+            let packed = &mut levels[pos.index()];
+            *packed = with_level(*packed, channel, level);
+        }
+    }
+    
+    /// Go back to storing a single light if every block has
+    /// the same light, as in open air under the sky.
+    pub fn compact(&mut self) {
+        let uniform = match self.0 {
+            Levels::Uniform(_) => return,
+            Levels::Dense(ref levels) if levels.iter().all(|&l| l == levels[0]) => levels[0],
+            Levels::Dense(_) => return,
+        };
+        
+        self.0 = Levels::Uniform(uniform);
+    }
+}
+
+// Replace the level of one channel in packed light.
+fn with_level(packed: u8, channel: Channel, level: u8) -> u8 {
+    match channel {
+        Channel::Sky => (packed & 0xf) | (level << 4),
+        Channel::Block => (packed & 0xf0) | level,
+    }
+}
+
+type Sectors = HashMap<(i32, i32, i32), Sector>;
+
+/// The light of the neighbors of a sector, in the order
+/// of `FACES`. Neighbors that are not loaded are `None`.
+pub type NeighborLight = [Option<Arc<LightList>>; 6];
+
+// The index of the neighbor above in a `NeighborLight`.
+const ABOVE: usize = 2;
+
+/// Light the blocks of a sector that is about to be loaded, from
+/// its own light sources and the light of its neighbors. Light
+/// that leaves the sector is spread by `light_sector` once it is
+/// loaded. Set `open_sky` if the sector is above the surface.
+pub fn light_blocks(blocks: &BlockList, neighbors: &NeighborLight, open_sky: bool,
+                    registry: &BlockRegistry) -> LightList {
+    let mut light = LightList::dark();
+    let top = SECTOR_SIZE as u8 - 1;
+    
+    for &channel in &CHANNELS {
+        let mut spread = VecDeque::new();
+        
+        // Light from the sources in the sector itself.
+        for (pos, &block) in blocks {
+            let level = match channel {
+                Channel::Block => registry.light(block),
+                Channel::Sky => {
+                    let open = open_sky && neighbors[ABOVE].is_none() && pos.y() == top;
+                    
+                    if open && registry.is_transparent(block) { MAX_LIGHT } else { 0 }
+                },
+            };
+            
+            if level > 0 {
+                light.set(pos, channel, level);
+                spread.push_back(pos);
+            }
+        }
+        
+        // Light that enters the sector from its neighbors.
+        for (&face, next) in FACES.iter().zip(neighbors) {
+            let next = match *next {
+                Some(ref next) => next,
+                None => continue,
+            };
+            
+            for pos in side(face) {
+                let level = fade(channel, face.opposite(), next.get(across(pos, face), channel));
+                
+                if registry.is_transparent(*blocks.get(pos)) && light.get(pos, channel) < level {
+                    light.set(pos, channel, level);
+                    spread.push_back(pos);
+                }
+            }
+        }
+        
+        while let Some(pos) = spread.pop_front() {
+            let level = light.get(pos, channel);
+            
+            for &face in &FACES {
+                let next_level = fade(channel, face, level);
+                let next = match step(pos, face) {
+                    Some(next) if next_level > 0 => next,
+                    _ => continue,
+                };
+                
+                if registry.is_transparent(*blocks.get(next)) &&
+                   light.get(next, channel) < next_level {
+                    light.set(next, channel, next_level);
+                    spread.push_back(next);
+                }
+            }
+        }
+    }
+    
+    light.compact();
+    
+    light
+}
+
+/// Determine if the light that `light_blocks` found for a sector
+/// may be too bright, because light has since been taken away from
+/// the sides of its neighbors that face it, or because it was lit as
+/// open to the sky and the sector above it has been loaded since.
+/// Light that was added since is spread by `light_sector`.
+pub fn is_stale(sectors: &Sectors, coords: (i32, i32, i32), neighbors: &NeighborLight,
+                open_sky: bool) -> bool {
+    let above = (coords.0, coords.1 + 1, coords.2);
+    if open_sky && neighbors[ABOVE].is_none() && sectors.contains_key(&above) {
+        return true;
+    }
+    
+    FACES.iter().zip(neighbors).any(|(&face, old)| {
+        let normal = face.normal();
+        let next = (coords.0 + normal.0, coords.1 + normal.1, coords.2 + normal.2);
+        
+        let (old, new) = match (old.as_ref(), sectors.get(&next)) {
+            (Some(old), Some(sector)) => (old, sector.light()),
+            _ => return false,
+        };
+        
+        !ptr::eq(&**old, new) && side(face.opposite()).into_iter().any(|pos| {
+            CHANNELS.iter().any(|&channel| new.get(pos, channel) < old.get(pos, channel))
+        })
+    })
+}
+
+/// Spread light between a sector that was just loaded and its
+/// neighbors. The light within the sector must have been found
+/// by `light_blocks`. Returns the sectors whose meshes depend
+/// on light that changed.
+pub fn light_sector(sectors: &mut Sectors, registry: &BlockRegistry,
+                    coords: (i32, i32, i32)) -> HashSet<(i32, i32, i32)> {
+    let mut lighting = Lighting::new(sectors, registry);
+    let size = SECTOR_SIZE as i32;
+    let origin = (coords.0 * size, coords.1 * size, coords.2 * size);
+    
+    for &channel in &CHANNELS {
+        let mut spread = VecDeque::new();
+        
+        // The sector below no longer gets its sky light straight from
+        // the sky, so the light it assumed from above is removed.
+        let below = (coords.0, coords.1 - 1, coords.2);
+        let below_open = lighting.sectors.get(&below).is_some_and(|s| s.is_open_sky());
+        
+        if channel == Channel::Sky && below_open {
+            let mut dark = VecDeque::new();
+            
+            for x in 0..size {
+                for z in 0..size {
+                    let pos = (origin.0 + x, origin.1 - 1, origin.2 + z);
+                    
+                    if let Some(level) = lighting.get(pos, channel) {
+                        if level > 0 {
+                            lighting.set(pos, channel, 0);
+                            dark.push_back((pos, level));
+                        }
+                    }
+                }
+            }
+            
+            lighting.darken(channel, dark, &mut spread);
+        }
+        
+        // Light that crosses the sides of the sector, in either
+        // direction. The neighbors may also have gained light since
+        // the sector was lit, which enters it here. Only the blocks
+        // that light the block across the side are spread from.
+        for &face in &FACES {
+            let normal = face.normal();
+            
+            for local in side(face) {
+                let inside = (origin.0 + local.x() as i32,
+                              origin.1 + local.y() as i32,
+                              origin.2 + local.z() as i32);
+                let outside = (inside.0 + normal.0, inside.1 + normal.1, inside.2 + normal.2);
+                
+                let (level, outside_level) = match (lighting.get(inside, channel),
+                                                    lighting.get(outside, channel)) {
+                    (Some(level), Some(outside_level)) => (level, outside_level),
+                    _ => continue,
+                };
+                
+                if fade(channel, face, level) > outside_level && lighting.is_open(outside) {
+                    spread.push_back(inside);
+                }
+                
+                if fade(channel, face.opposite(), outside_level) > level &&
+                   lighting.is_open(inside) {
+                    spread.push_back(outside);
+                }
+            }
+        }
+        
+        lighting.spread(channel, spread);
+    }
+    
+    lighting.changed
+}
+
+/// Update the light around a block that changed, at **world**
+/// block coords. Returns the sectors whose meshes depend on
+/// light that changed.
+pub fn light_block(sectors: &mut Sectors, registry: &BlockRegistry,
+                   pos: (i32, i32, i32)) -> HashSet<(i32, i32, i32)> {
+    let mut lighting = Lighting::new(sectors, registry);
+    
+    for &channel in &CHANNELS {
+        let old = match lighting.get(pos, channel) {
+            Some(level) => level,
+            None => continue,
+        };
+        
+        // Remove the light that passed through the block.
+        let mut spread = VecDeque::new();
+        let mut dark = VecDeque::new();
+        lighting.set(pos, channel, 0);
+        dark.push_back((pos, old));
+        lighting.darken(channel, dark, &mut spread);
+        
+        // The block may give off light of its own.
+        let level = lighting.source(pos, channel);
+        if level > 0 {
+            lighting.set(pos, channel, level);
+            spread.push_back(pos);
+        }
+        
+        // Light from the neighbors may now enter the block.
+        for &face in &FACES {
+            let next = offset(pos, face);
+            if lighting.get(next, channel).is_some_and(|level| level > 0) {
+                spread.push_back(next);
+            }
+        }
+        
+        lighting.spread(channel, spread);
+    }
+    
+    lighting.changed
+}
+
+// The state of one lighting update.
+struct Lighting<'a> {
+    sectors: &'a mut Sectors,
+    registry: &'a BlockRegistry,
+    
+    // The sectors whose meshes depend on light that changed.
+    changed: HashSet<(i32, i32, i32)>,
+}
+
+impl<'a> Lighting<'a> {
+    fn new(sectors: &'a mut Sectors, registry: &'a BlockRegistry) -> Lighting<'a> {
+        Lighting {
+            sectors,
+            registry,
+            changed: HashSet::new(),
+        }
+    }
+    
+    // The light at **world** block coords, if the sector is loaded.
+    fn get(&self, pos: (i32, i32, i32), channel: Channel) -> Option<u8> {
+        let (sector, local) = split_world_coords(pos);
+        
+        self.sectors.get(&sector).map(|s| s.light().get(local, channel))
+    }
+    
+    fn set(&mut self, pos: (i32, i32, i32), channel: Channel, level: u8) {
+        let (sector, local) = split_world_coords(pos);
+        
+        if let Some(s) = self.sectors.get_mut(&sector) {
+            s.light_mut().set(local, channel, level);
+        } else {
+            return;
+        }
+        
+        self.mark_changed(sector, local);
+    }
+    
+    // Record that the light of a block changed.
+    fn mark_changed(&mut self, sector: (i32, i32, i32), local: SectorSpaceCoords) {
+        // Faces in the neighboring sectors sample
+        // the light of the blocks on the boundary.
+        let max = SECTOR_SIZE as u8 - 1;
+        let (x, y, z) = sector;
+        
+        self.changed.insert(sector);
+        if local.x() == 0   { self.changed.insert((x - 1, y, z)); }
+        if local.x() == max { self.changed.insert((x + 1, y, z)); }
+        if local.y() == 0   { self.changed.insert((x, y - 1, z)); }
+        if local.y() == max { self.changed.insert((x, y + 1, z)); }
+        if local.z() == 0   { self.changed.insert((x, y, z - 1)); }
+        if local.z() == max { self.changed.insert((x, y, z + 1)); }
+    }
+    
+    // Determine if light can enter the block at **world** block
+    // coords. Blocks in sectors that are not loaded are closed.
+    fn is_open(&self, pos: (i32, i32, i32)) -> bool {
+        let (sector, local) = split_world_coords(pos);
+        
+        self.sectors.get(&sector).is_some_and(|s| {
+            self.registry.is_transparent(*s.blocks().get(local))
+        })
+    }
+    
+    // The light that a block produces by itself.
+    fn source(&self, pos: (i32, i32, i32), channel: Channel) -> u8 {
+        let (sector, local) = split_world_coords(pos);
+        let block = match self.sectors.get(&sector) {
+            Some(s) => *s.blocks().get(local),
+            None => return 0,
+        };
+        
+        match channel {
+            Channel::Block => self.registry.light(block),
+            Channel::Sky => {
+                let above = (sector.0, sector.1 + 1, sector.2);
+                let open_sky = local.y() as usize == SECTOR_SIZE - 1 &&
+                               self.sectors[&sector].is_open_sky() &&
+                               !self.sectors.contains_key(&above);
+                
+                if open_sky && self.registry.is_transparent(block) {
+                    MAX_LIGHT
+                } else {
+                    0
+                }
+            },
+        }
+    }
+    
+    // Spread light outward from the blocks in `spread`.
+    fn spread(&mut self, channel: Channel, mut spread: VecDeque<(i32, i32, i32)>) {
+        while let Some(pos) = spread.pop_front() {
+            let (sector_coords, local) = split_world_coords(pos);
+            let level = match self.get(pos, channel) {
+                Some(level) => level,
+                None => continue,
+            };
+            
+            for &face in &FACES {
+                let next_level = fade(channel, face, level);
+                if next_level == 0 {
+                    continue;
+                }
+                
+                let next = offset(pos, face);
+                
+                // Most light stays in the same sector,
+                // which is faster to reach than the others.
+                if let Some(next_local) = step(local, face) {
+                    let registry = self.registry;
+                    let lit = {
+                        let sector = self.sectors.get_mut(&sector_coords).unwrap();
+                        let open = registry.is_transparent(*sector.blocks().get(next_local));
+                        
+                        if open && sector.light().get(next_local, channel) < next_level {
+                            sector.light_mut().set(next_local, channel, next_level);
+                            true
+                        } else {
+                            false
+                        }
+                    };
+                    
+                    if lit {
+                        self.mark_changed(sector_coords, next_local);
+                        spread.push_back(next);
+                    }
+                    
+                    continue;
+                }
+                
+                if !self.is_open(next) {
+                    continue;
+                }
+                
+                if self.get(next, channel).is_some_and(|l| l < next_level) {
+                    self.set(next, channel, next_level);
+                    spread.push_back(next);
+                }
+            }
+        }
+    }
+    
+    // Remove the light that came from the blocks in `dark`,
+    // which have already been darkened and hold their previous
+    // light. Blocks that are lit from elsewhere, along with
+    // light sources, are added to `spread` to fill in the gap.
+    fn darken(&mut self, channel: Channel, mut dark: VecDeque<((i32, i32, i32), u8)>,
+              spread: &mut VecDeque<(i32, i32, i32)>) {
+        while let Some((pos, level)) = dark.pop_front() {
+            for &face in &FACES {
+                let next = offset(pos, face);
+                let next_level = match self.get(next, channel) {
+                    Some(l) => l,
+                    None => continue,
+                };
+                
+                if next_level == 0 {
+                    continue;
+                }
+                
+                if next_level == fade(channel, face, level) || next_level < level {
+                    self.set(next, channel, 0);
+                    dark.push_back((next, next_level));
+                    
+                    let source = self.source(next, channel);
+                    if source > 0 {
+                        self.set(next, channel, source);
+                        spread.push_back(next);
+                    }
+                } else {
+                    spread.push_back(next);
+                }
+            }
+        }
+    }
+}
+
+// The light that reaches the next block across a face.
+fn fade(channel: Channel, face: Face, level: u8) -> u8 {
+    if channel == Channel::Sky && face == Face::Bottom && level == MAX_LIGHT {
+        MAX_LIGHT
+    } else {
+        level.saturating_sub(1)
+    }
+}
+
+// The block next to `local` across a face,
+// if it is in the same sector.
+fn step(local: SectorSpaceCoords, face: Face) -> Option<SectorSpaceCoords> {
+    match face {
+        Face::Back => local.back(),
+        Face::Front => local.front(),
+        Face::Top => local.top(),
+        Face::Bottom => local.bottom(),
+        Face::Left => local.left(),
+        Face::Right => local.right(),
+    }
+}
+
+// The blocks on the side of a sector across a face.
+fn side(face: Face) -> Vec<SectorSpaceCoords> {
+    let last = SECTOR_SIZE as u8 - 1;
+    let normal = face.normal();
+    let fixed = |n: i32, c: u8| match n {
+        1 => last,
+        -1 => 0,
+        _ => c,
+    };
+    
+    let mut blocks = Vec::with_capacity(SECTOR_SIZE * SECTOR_SIZE);
+    for a in 0..last + 1 {
+        for b in 0..last + 1 {
+            let (x, y, z) = match normal {
+                (0, 0, _) => (a, b, 0),
+                (0, _, 0) => (a, 0, b),
+                _ => (0, a, b),
+            };
+            
+            blocks.push(SectorSpaceCoords::new(fixed(normal.0, x), fixed(normal.1, y),
+                                               fixed(normal.2, z)));
+        }
+    }
+    
+    blocks
+}
+
+// The block in the next sector across a face,
+// from a block on that side of its own sector.
+fn across(local: SectorSpaceCoords, face: Face) -> SectorSpaceCoords {
+    let last = SECTOR_SIZE as u8 - 1;
+    let normal = face.normal();
+    let wrap = |n: i32, c: u8| match n {
+        1 => 0,
+        -1 => last,
+        _ => c,
+    };
+    
+    SectorSpaceCoords::new(wrap(normal.0, local.x()), wrap(normal.1, local.y()),
+                           wrap(normal.2, local.z()))
+}
+
+// The block next to `pos` across a face.
+fn offset(pos: (i32, i32, i32), face: Face) -> (i32, i32, i32) {
+    let normal = face.normal();
+    
+    (pos.0 + normal.0, pos.1 + normal.1, pos.2 + normal.2)
+}
+
+#[cfg(test)]
+mod tests {
+    use terrain::voxel::BlockId;
+    use super::*;
+    
+    const BLOCKS: &str = "
+[air]
+id = 0
+solid = false
+transparent = true
+
+[stone]
+id = 1
+
+[torch]
+id = 2
+solid = false
+transparent = true
+light = 14
+";
+
+    fn registry() -> BlockRegistry {
+        BlockRegistry::load(BLOCKS.as_bytes()).unwrap()
+    }
+    
+    // The light of the loaded neighbors of a sector.
+    fn neighbors(sectors: &Sectors, coords: (i32, i32, i32)) -> NeighborLight {
+        let mut neighbors = NeighborLight::default();
+        for (next, &face) in neighbors.iter_mut().zip(&FACES) {
+            *next = sectors.get(&offset(coords, face)).map(|s| s.light_snapshot());
+        }
+        
+        neighbors
+    }
+    
+    // Light and load a sector, as the world does.
+    fn load(sectors: &mut Sectors, registry: &BlockRegistry, coords: (i32, i32, i32),
+            blocks: BlockList, open_sky: bool) {
+        let neighbors = neighbors(sectors, coords);
+        let light = light_blocks(&blocks, &neighbors, open_sky, registry);
+        assert!(!is_stale(sectors, coords, &neighbors, open_sky));
+        
+        let mut sector = Sector::new(blocks, light);
+        if open_sky {
+            sector.mark_open_sky();
+        }
+        
+        sectors.insert(coords, sector);
+        light_sector(sectors, registry, coords);
+    }
+    
+    // Change a block at world coords, as the world does.
+    fn set_block(sectors: &mut Sectors, registry: &BlockRegistry, pos: (i32, i32, i32),
+                 block: BlockId) {
+        let (sector, local) = split_world_coords(pos);
+        sectors.get_mut(&sector).unwrap().set_block(local, block);
+        light_block(sectors, registry, pos);
+    }
+    
+    // The light at world coords.
+    fn level(sectors: &Sectors, pos: (i32, i32, i32), channel: Channel) -> u8 {
+        let (sector, local) = split_world_coords(pos);
+        
+        sectors[&sector].light().get(local, channel)
+    }
+    
+    fn is_uniform(light: &LightList) -> bool {
+        match light.0 {
+            Levels::Uniform(_) => true,
+            Levels::Dense(_) => false,
+        }
+    }
+    
+    #[test]
+    fn uniform_storage() {
+        let pos = SectorSpaceCoords::new(1, 2, 3);
+        let mut light = LightList::dark();
+        
+        light.set(pos, Channel::Sky, 0);
+        assert!(is_uniform(&light));
+        
+        light.set(pos, Channel::Block, 7);
+        assert!(!is_uniform(&light));
+        assert_eq!(light.get(pos, Channel::Block), 7);
+        assert_eq!(light.get(pos, Channel::Sky), 0);
+        assert_eq!(light.get(SectorSpaceCoords::new(0, 0, 0), Channel::Block), 0);
+        
+        light.compact();
+        assert!(!is_uniform(&light));
+        
+        light.set(pos, Channel::Block, 0);
+        light.compact();
+        assert!(is_uniform(&light));
+        
+        // Open air under the sky is lit everywhere.
+        let registry = registry();
+        let light = light_blocks(&BlockList::new_air(), &NeighborLight::default(), true,
+                                 &registry);
+        assert!(is_uniform(&light));
+        assert_eq!(light.get(pos, Channel::Sky), MAX_LIGHT);
+    }
+    
+    #[test]
+    fn sky_light_falls_through_sectors() {
+        let registry = registry();
+        let mut sectors = Sectors::new();
+        
+        // The sectors below are dark until the sky reaches them.
+        load(&mut sectors, &registry, (0, 0, 0), BlockList::new_air(), false);
+        load(&mut sectors, &registry, (0, 1, 0), BlockList::new_air(), false);
+        assert_eq!(level(&sectors, (5, 40, 5), Channel::Sky), 0);
+        
+        load(&mut sectors, &registry, (0, 2, 0), BlockList::new_air(), true);
+        for &y in &[95, 64, 40, 0] {
+            assert_eq!(level(&sectors, (5, y, 5), Channel::Sky), MAX_LIGHT);
+        }
+    }
+    
+    #[test]
+    fn sector_above_makes_open_sky_stale() {
+        let registry = registry();
+        let mut sectors = Sectors::new();
+        let old = neighbors(&sectors, (0, 0, 0));
+        
+        load(&mut sectors, &registry, (0, 1, 0), BlockList::new_air(), true);
+        assert!(is_stale(&sectors, (0, 0, 0), &old, true));
+        assert!(!is_stale(&sectors, (0, 0, 0), &old, false));
+    }
+    
+    #[test]
+    fn block_light_crosses_sectors() {
+        let registry = registry();
+        let mut sectors = Sectors::new();
+        let torch = registry.id("torch").unwrap();
+        let mut blocks = BlockList::new_air();
+        blocks.set(SectorSpaceCoords::new(30, 5, 5), torch);
+        
+        load(&mut sectors, &registry, (0, 0, 0), blocks, false);
+        load(&mut sectors, &registry, (1, 0, 0), BlockList::new_air(), false);
+        
+        assert_eq!(level(&sectors, (30, 5, 5), Channel::Block), 14);
+        assert_eq!(level(&sectors, (32, 5, 5), Channel::Block), 12);
+        assert_eq!(level(&sectors, (40, 5, 5), Channel::Block), 4);
+        assert_eq!(level(&sectors, (34, 6, 5), Channel::Block), 9);
+        
+        // A torch placed by the player also lights the neighbor.
+        set_block(&mut sectors, &registry, (31, 20, 5), torch);
+        assert_eq!(level(&sectors, (33, 20, 5), Channel::Block), 12);
+    }
+    
+    #[test]
+    fn removing_light_darkens() {
+        let registry = registry();
+        let mut sectors = Sectors::new();
+        let torch = registry.id("torch").unwrap();
+        let mut blocks = BlockList::new_air();
+        blocks.set(SectorSpaceCoords::new(30, 5, 5), torch);
+        
+        load(&mut sectors, &registry, (0, 0, 0), blocks, false);
+        load(&mut sectors, &registry, (1, 0, 0), BlockList::new_air(), false);
+        
+        set_block(&mut sectors, &registry, (30, 5, 5), BlockId::AIR);
+        for &pos in &[(30, 5, 5), (29, 5, 5), (32, 5, 5), (40, 5, 5), (30, 15, 5)] {
+            assert_eq!(level(&sectors, pos, Channel::Block), 0);
+        }
+        
+        assert!(sectors.values().all(|s| {
+            CHANNELS.iter().all(|&c| s.blocks().into_iter().all(|(p, _)| s.light().get(p, c) == 0))
+        }));
+    }
+    
+    #[test]
+    fn block_under_sky_casts_shadow() {
+        let registry = registry();
+        let mut sectors = Sectors::new();
+        load(&mut sectors, &registry, (0, 0, 0), BlockList::new_air(), true);
+        load(&mut sectors, &registry, (0, -1, 0), BlockList::new_air(), false);
+        
+        let stone = registry.id("stone").unwrap();
+        set_block(&mut sectors, &registry, (5, 20, 5), stone);
+        assert_eq!(level(&sectors, (5, 20, 5), Channel::Sky), 0);
+        assert_eq!(level(&sectors, (5, 21, 5), Channel::Sky), MAX_LIGHT);
+        assert_eq!(level(&sectors, (6, 19, 5), Channel::Sky), MAX_LIGHT);
+        
+        // Under the block, only light from the sides is left,
+        // in this sector and in the one below.
+        for &y in &[19, 0, -1, -30] {
+            assert_eq!(level(&sectors, (5, y, 5), Channel::Sky), MAX_LIGHT - 1);
+        }
+        
+        set_block(&mut sectors, &registry, (5, 20, 5), BlockId::AIR);
+        for &y in &[20, 19, 0, -30] {
+            assert_eq!(level(&sectors, (5, y, 5), Channel::Sky), MAX_LIGHT);
+        }
+    }
+}
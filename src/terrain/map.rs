@@ -0,0 +1,342 @@
+//! Maps of the ground from above, written as PNG images.
+//!
+//! Maps are sampled straight from a `WorldGenerator`, without
+//! loading the world or opening a window, so that the shape of
+//! the terrain can be checked quickly.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use png::{self, BitDepth, ColorType, Decoder, Encoder};
+use super::{split_world_coords, SECTOR_SIZE};
+use super::atlas::{Atlas, TILE_SIZE};
+use super::biome::Biome;
+use super::mesh_gen::Face;
+use super::registry::BlockRegistry;
+use super::voxel::{BlockId, BlockList, SectorSpaceCoords};
+use super::world_gen::{WorldGen, WorldGenerator};
+
+/// A rectangle of columns of blocks, in **world** block coords.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MapArea {
+    /// The lowest x coordinate, which is the left edge of the map.
+    pub x: i32,
+    
+    /// The lowest z coordinate, which is the top edge of the map.
+    pub z: i32,
+    
+    /// The number of columns along the x axis.
+    pub width: u32,
+    
+    /// The number of columns along the z axis.
+    pub depth: u32,
+}
+
+impl MapArea {
+    // The index of a column at **world** block coords,
+    // counting row by row from the lowest x and z.
+    fn index(&self, x: i32, z: i32) -> Option<usize> {
+        let (x, z) = (x - self.x, z - self.z);
+        
+        if x >= 0 && z >= 0 && x < self.width as i32 && z < self.depth as i32 {
+            Some(z as usize * self.width as usize + x as usize)
+        } else {
+            None
+        }
+    }
+}
+
+/// The ground in every column of an area, as seen from above.
+pub struct SurfaceMap {
+    area: MapArea,
+    heights: Vec<i32>,
+    blocks: Vec<BlockId>,
+}
+
+impl SurfaceMap {
+    /// Sample the ground in `area`. The height of every column comes
+    /// from `WorldGenerator::height_at`, and the block on top from
+    /// the sectors that hold the surface, so that trees and caves
+    /// that break through the ground are seen as well.
+    pub fn sample(gen: &dyn WorldGenerator, area: MapArea) -> SurfaceMap {
+        let len = area.width as usize * area.depth as usize;
+        let mut heights = Vec::with_capacity(len);
+        
+        for z in 0..area.depth as i32 {
+            for x in 0..area.width as i32 {
+                heights.push(gen.height_at(area.x + x, area.z + z));
+            }
+        }
+        
+        let mut blocks = vec![BlockId::AIR; len];
+        
+        if len > 0 {
+            let size = SECTOR_SIZE as i32;
+            let (first, _) = split_world_coords((area.x, 0, area.z));
+            let (last, _) = split_world_coords((area.x + area.width as i32 - 1, 0,
+                                                area.z + area.depth as i32 - 1));
+            
+            for sector_z in first.2..last.2 + 1 {
+                for sector_x in first.0..last.0 + 1 {
+                    let (low, high) = gen.surface_rows(sector_x, sector_z);
+                    
+                    // The columns of the area in this column of sectors.
+                    let min_x = (sector_x * size).max(area.x);
+                    let max_x = (sector_x * size + size).min(area.x + area.width as i32);
+                    let min_z = (sector_z * size).max(area.z);
+                    let max_z = (sector_z * size + size).min(area.z + area.depth as i32);
+                    
+                    // Look down from the highest row until
+                    // every column has found the ground.
+                    let mut left = (max_x - min_x) * (max_z - min_z);
+                    let mut row = high;
+                    
+                    while left > 0 && row >= low {
+                        let list = gen.generate((sector_x, row, sector_z));
+                        
+                        for z in min_z..max_z {
+                            for x in min_x..max_x {
+                                let index = ((z - area.z) * area.width as i32 + x - area.x) as usize;
+                                if !blocks[index].is_air() {
+                                    continue;
+                                }
+                                
+                                if let Some(block) = top_block(&list, x - sector_x * size,
+                                                               z - sector_z * size) {
+                                    blocks[index] = block;
+                                    left -= 1;
+                                }
+                            }
+                        }
+                        
+                        row -= 1;
+                    }
+                }
+            }
+        }
+        
+        SurfaceMap {
+            area,
+            heights,
+            blocks,
+        }
+    }
+    
+    /// The area that the map covers.
+    pub fn area(&self) -> MapArea {
+        self.area
+    }
+    
+    /// The height of the ground at **world** block coords,
+    /// if they are in the area of the map.
+    pub fn height_at(&self, x: i32, z: i32) -> Option<i32> {
+        self.area.index(x, z).map(|i| self.heights[i])
+    }
+    
+    /// The block on top of the column at **world** block coords,
+    /// if they are in the area of the map. Columns without any
+    /// blocks in the sectors that hold the surface are air.
+    pub fn block_at(&self, x: i32, z: i32) -> Option<BlockId> {
+        self.area.index(x, z).map(|i| self.blocks[i])
+    }
+    
+    /// The lowest and the highest ground in the map.
+    /// An empty map has a range of `(0, 0)`.
+    pub fn height_range(&self) -> (i32, i32) {
+        let lowest = self.heights.iter().cloned().min().unwrap_or(0);
+        let highest = self.heights.iter().cloned().max().unwrap_or(0);
+        
+        (lowest, highest)
+    }
+    
+    /// Write the heights as a grayscale PNG image, with the lowest
+    /// ground in black and the highest in white, as given by
+    /// `height_range`. Each pixel is one column, with x to the
+    /// right and z downward.
+    /// # Errors
+    /// Fails if the image cannot be written.
+    pub fn write_heightmap<W: Write>(&self, out: W) -> io::Result<()> {
+        let (lowest, highest) = self.height_range();
+        let range = (highest - lowest).max(1) as f32;
+        
+        let pixels: Vec<u8> = self.heights.iter().map(|&height| {
+            ((height - lowest) as f32 / range * 255.).round() as u8
+        }).collect();
+        
+        write_png(out, self.area, ColorType::Grayscale, &pixels)
+    }
+    
+    /// Write the block on top of every column as an RGB PNG
+    /// image, with each block in its color from `colors`. Blocks
+    /// without a color are black. Each pixel is one column,
+    /// with x to the right and z downward.
+    /// # Errors
+    /// Fails if the image cannot be written.
+    pub fn write_blocks<W: Write>(&self, out: W, colors: &BlockColors) -> io::Result<()> {
+        let mut pixels = Vec::with_capacity(self.blocks.len() * 3);
+        
+        for &block in &self.blocks {
+            pixels.extend_from_slice(&colors.get(block));
+        }
+        
+        write_png(out, self.area, ColorType::RGB, &pixels)
+    }
+}
+
+/// The biome of every column of an area, as seen from above.
+pub struct BiomeMap {
+    area: MapArea,
+    biomes: Vec<Biome>,
+}
+
+impl BiomeMap {
+    /// Sample the biome of every column in `area`, which
+    /// is the biome that weighs the most in the column.
+    pub fn sample(gen: &WorldGen, area: MapArea) -> BiomeMap {
+        let mut biomes = Vec::with_capacity(area.width as usize * area.depth as usize);
+        
+        for z in 0..area.depth as i32 {
+            for x in 0..area.width as i32 {
+                biomes.push(gen.biome_at(area.x + x, area.z + z));
+            }
+        }
+        
+        BiomeMap {
+            area,
+            biomes,
+        }
+    }
+    
+    /// The biome of the column at **world** block coords,
+    /// if they are in the area of the map.
+    pub fn biome_at(&self, x: i32, z: i32) -> Option<Biome> {
+        self.area.index(x, z).map(|i| self.biomes[i])
+    }
+    
+    /// Write the biomes as an RGB PNG image, with each biome in a
+    /// color of its own. Each pixel is one column, with x to the
+    /// right and z downward.
+    /// # Errors
+    /// Fails if the image cannot be written.
+    pub fn write<W: Write>(&self, out: W) -> io::Result<()> {
+        let mut pixels = Vec::with_capacity(self.biomes.len() * 3);
+        
+        for &biome in &self.biomes {
+            pixels.extend_from_slice(&biome_color(biome));
+        }
+        
+        write_png(out, self.area, ColorType::RGB, &pixels)
+    }
+}
+
+// The color of a biome on a map.
+fn biome_color(biome: Biome) -> [u8; 3] {
+    match biome {
+        Biome::Plains => [140, 190, 90],
+        Biome::Forest => [40, 110, 40],
+        Biome::Desert => [220, 200, 130],
+        Biome::Mountains => [130, 130, 130],
+        Biome::Tundra => [235, 240, 245],
+    }
+}
+
+// Write an 8 bit image of an area, with one pixel per column.
+fn write_png<W: Write>(out: W, area: MapArea, color: ColorType,
+                       pixels: &[u8]) -> io::Result<()> {
+    let mut encoder = Encoder::new(out, area.width, area.depth);
+    encoder.set_color(color);
+    encoder.set_depth(BitDepth::Eight);
+    
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(pixels)?;
+    
+    Ok(())
+}
+
+/// The color of every block on a map, which is
+/// the average color of its top face in the atlas.
+pub struct BlockColors(HashMap<BlockId, [u8; 3]>);
+
+impl BlockColors {
+    /// Find the color of every block in `registry`
+    /// from the atlas image and its layout.
+    /// # Errors
+    /// Fails if the image or the layout cannot be read,
+    /// or if the image is not 8 bit RGB or RGBA.
+    pub fn load<I: Read, L: Read>(image: I, layout: L,
+                                  registry: &BlockRegistry) -> io::Result<BlockColors> {
+        let (info, mut reader) = Decoder::new(image).read_info().map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+        })?;
+        
+        let channels = match (info.color_type, info.bit_depth) {
+            (png::ColorType::RGB, BitDepth::Eight) => 3,
+            (png::ColorType::RGBA, BitDepth::Eight) => 4,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                           "the atlas image must be 8 bit RGB or RGBA")),
+        };
+        
+        let mut pixels = vec![0; info.buffer_size()];
+        reader.next_frame(&mut pixels).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+        })?;
+        
+        let atlas = Atlas::load(layout, &info, registry)?;
+        let mut colors = HashMap::new();
+        
+        for block in registry.iter() {
+            let tile = match atlas.tile(block.id(), Face::Top) {
+                Some(tile) => tile,
+                None => continue,
+            };
+            
+            let left = (tile[0] * info.width as f32).round() as usize;
+            let top = (tile[1] * info.height as f32).round() as usize;
+            let mut sum = [0; 3];
+            let mut count = 0;
+            
+            // Fully transparent pixels, such as the
+            // holes in leaves, are left out.
+            for y in top..top + TILE_SIZE as usize {
+                for x in left..left + TILE_SIZE as usize {
+                    let pixel = (y * info.width as usize + x) * channels;
+                    
+                    if channels == 4 && pixels[pixel + 3] == 0 {
+                        continue;
+                    }
+                    
+                    for (channel, total) in sum.iter_mut().enumerate() {
+                        *total += pixels[pixel + channel] as u32;
+                    }
+                    count += 1;
+                }
+            }
+            
+            let count = count.max(1);
+            colors.insert(block.id(), [(sum[0] / count) as u8,
+                                       (sum[1] / count) as u8,
+                                       (sum[2] / count) as u8]);
+        }
+        
+        Ok(BlockColors(colors))
+    }
+    
+    /// The color of a block. Blocks that are
+    /// not drawn, such as air, are black.
+    pub fn get(&self, block: BlockId) -> [u8; 3] {
+        self.0.get(&block).cloned().unwrap_or([0; 3])
+    }
+}
+
+// The highest block that is not air in one column
+// of a sector, at sector coords.
+fn top_block(list: &BlockList, x: i32, z: i32) -> Option<BlockId> {
+    for y in (0..SECTOR_SIZE).rev() {
+        let block = *list.get(SectorSpaceCoords::new(x as u8, y as u8, z as u8));
+        
+        if !block.is_air() {
+            return Some(block);
+        }
+    }
+    
+    None
+}
@@ -1,9 +1,16 @@
 //! This module contains the logic for creating tesselations
 //! from `Sector`.
 
-use png::OutputInfo;
-use super::{Position, UV, Vertex, SECTOR_SIZE};
-use super::voxel::{AdjacentSectors, Block, BlockList, SectorSpaceCoords};
+use std::collections::HashMap;
+
+use super::{Position, Vertex, SECTOR_SIZE};
+use super::atlas::Atlas;
+use super::light::{Channel, LightList};
+use super::registry::{BlockRegistry, RenderPass};
+use super::shape::{BlockBox, BlockShape};
+use super::vertex::{VertexData, CROSS_FACE};
+use super::voxel::{Adjacent, AdjacentLight, AdjacentSectors, BlockId, BlockList,
+                   SectorSpaceCoords};
 
 /*
 const OFFSETS: [Position; 3] = [
@@ -35,10 +42,24 @@ const UVS: (UV, UV, UV, UV) = (
 );
 */
 
-const TILE_SIZE: f32 = 16.;
+// The box of a single block.
+const UNIT: BlockBox = BlockBox { min: [0.0, 0.0, 0.0], max: [1.0, 1.0, 1.0] };
+
+/// Selects how the mesh of a `Sector` is built.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MeshMode {
+    /// One quad for every visible block face.
+    Simple,
+    
+    /// Visible faces of the same block that lie in the
+    /// same plane are merged into larger quads.
+    Greedy,
+}
 
-#[derive(Clone, Copy)]
-enum Face {
+/// A face of a block. Back and front face along -Z and +Z,
+/// bottom and top along -Y and +Y, left and right along -X and +X.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Face {
     Back,
     Front,
     Top,
@@ -47,219 +68,908 @@ enum Face {
     Right,
 }
 
+impl Face {
+    /// The offset to the block that touches this face.
+    pub fn normal(&self) -> (i32, i32, i32) {
+        use self::Face::*;
+        
+        match *self {
+            Back => (0, 0, -1),
+            Front => (0, 0, 1),
+            Top => (0, 1, 0),
+            Bottom => (0, -1, 0),
+            Left => (-1, 0, 0),
+            Right => (1, 0, 0),
+        }
+    }
+    
+    /// The face on the other side of a block.
+    pub fn opposite(&self) -> Face {
+        use self::Face::*;
+        
+        match *self {
+            Back => Front,
+            Front => Back,
+            Top => Bottom,
+            Bottom => Top,
+            Left => Right,
+            Right => Left,
+        }
+    }
+    
+    // The axis along which the face points, followed by the
+    // axes along which its U and V texture coordinates run.
+    fn axes(&self) -> (usize, usize, usize) {
+        use self::Face::*;
+        
+        match *self {
+            Back | Front => (2, 0, 1),
+            Top | Bottom => (1, 0, 2),
+            Left | Right => (0, 2, 1),
+        }
+    }
+}
+
+/// Every face of a block.
+pub const FACES: [Face; 6] = [Face::Back, Face::Front, Face::Top,
+                          Face::Bottom, Face::Left, Face::Right];
+
+/// A mesh made of quads, whose triangles index its vertices.
+/// Quads that meet at a corner with the same light, occlusion
+/// and tile share the vertex there, which is stored only once.
+#[derive(Clone, Debug, Default)]
+pub struct Mesh {
+    /// The vertices, each of them different.
+    pub vertices: Vec<Vertex>,
+    
+    /// The indices of the vertices of each triangle,
+    /// six for every quad.
+    pub indices: Vec<u32>,
+    
+    // The index of each vertex, while the mesh is generated.
+    shared: HashMap<Vertex, u32>,
+}
+
+impl Mesh {
+    /// Determine if the mesh has no quads.
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+    
+    // Add a quad from its corners in the order of `face_corners`.
+    // The quad is split along the diagonal joining its brighter
+    // corners, so that the occlusion is interpolated the same
+    // way in every direction.
+    fn push_quad(&mut self, corners: [Vertex; 4], occlusion: [u32; 4]) {
+        let mut index = [0; 4];
+        for (index, &corner) in index.iter_mut().zip(&corners) {
+            let vertices = &mut self.vertices;
+            *index = *self.shared.entry(corner).or_insert_with(|| {
+                vertices.push(corner);
+                vertices.len() as u32 - 1
+            });
+        }
+        
+        let order = if occlusion[0] + occlusion[2] >= occlusion[1] + occlusion[3] {
+            [0, 1, 2, 0, 2, 3]
+        } else {
+            [1, 2, 3, 1, 3, 0]
+        };
+        
+        self.indices.extend(order.iter().map(|&i| index[i]));
+    }
+}
+
+// Drop the tables used to share vertices once the meshes are done.
+fn finish(mut meshes: PassMeshes) -> PassMeshes {
+    for mesh in &mut meshes {
+        mesh.shared = HashMap::new();
+    }
+    
+    meshes
+}
+
+/// The meshes of a sector, with one for each `RenderPass`,
+/// indexed by the pass.
+pub type PassMeshes = [Mesh; 3];
+
+/// Generate the mesh for a `BlockList` using the chosen `MeshMode`.
+/// The light of the blocks is baked into the vertices.
+pub fn generate_vertices(mode: MeshMode, blocks: &BlockList, adjacent: &AdjacentSectors,
+                         light: &LightList, adjacent_light: &AdjacentLight,
+                         atlas: &Atlas, registry: &BlockRegistry) -> PassMeshes {
+    match mode {
+        MeshMode::Simple => generate_block_vertices(blocks, adjacent, light, adjacent_light,
+                                                    atlas, registry),
+        MeshMode::Greedy => generate_greedy_vertices(blocks, adjacent, light, adjacent_light,
+                                                     atlas, registry),
+    }
+}
+
 /// Generate the mesh for a `BlockList`.
 pub fn generate_block_vertices(blocks: &BlockList, adjacent: &AdjacentSectors,
-                               texture_info: &OutputInfo) -> Vec<Vertex> {
-    use self::Face::*;
-    
-    let mut v = Vec::with_capacity(SECTOR_SIZE * SECTOR_SIZE * SECTOR_SIZE * 24);
+                               light: &LightList, adjacent_light: &AdjacentLight,
+                               atlas: &Atlas, registry: &BlockRegistry) -> PassMeshes {
+    let mut v = PassMeshes::default();
     
     for i in blocks {
-        if !i.1.is_air() {
-            if should_create_face(Back, i.0, blocks, adjacent) {
-                generate_face(&mut v, i, Back, texture_info);
-            }
-            
-            if should_create_face(Front, i.0, blocks, adjacent) {
-                generate_face(&mut v, i, Front, texture_info);
-            }
-            
-            if should_create_face(Top, i.0, blocks, adjacent) {
-                generate_face(&mut v, i, Top, texture_info);
-            }
-            
-            if should_create_face(Bottom, i.0, blocks, adjacent) {
-                generate_face(&mut v, i, Bottom, texture_info);
-            }
+        if registry.needs_rendering(*i.1) && registry.shape(*i.1) == BlockShape::Cube {
+            let pass = registry.pass(*i.1) as usize;
             
-            if should_create_face(Left, i.0, blocks, adjacent) {
-                generate_face(&mut v, i, Left, texture_info);
-            }
-            
-            if should_create_face(Right, i.0, blocks, adjacent) {
-                generate_face(&mut v, i, Right, texture_info);
+            for &face in &FACES {
+                if should_create_face(face, i.0, blocks, adjacent, registry) {
+                    let occlusion = face_occlusion(face, i.0, blocks, adjacent, registry);
+                    let light = face_light(face, i.0, blocks, adjacent, light,
+                                           adjacent_light, registry);
+                    generate_face(&mut v[pass], i, face, UNIT, occlusion, light, atlas);
+                }
             }
         }
     }
     
+    generate_shape_vertices(&mut v, blocks, adjacent, light, adjacent_light, atlas, registry);
+    
     //generate_face(&mut v);
     
     //println!("done!");
     
-    v
+    finish(v)
 }
 
-fn should_create_face(face: Face, coord: SectorSpaceCoords,
-                      blocks: &BlockList, adjacent: &AdjacentSectors) -> bool {
-    use self::Face::*;
+/// Generate the mesh for a `BlockList`, merging the visible faces
+/// of the same block that lie in the same plane into rectangles.
+/// Only faces with the same ambient occlusion and light at every
+/// corner are merged, so that the shading is not stretched.
+/// Blocks that are not cubes are never merged.
+pub fn generate_greedy_vertices(blocks: &BlockList, adjacent: &AdjacentSectors,
+                                light: &LightList, adjacent_light: &AdjacentLight,
+                                atlas: &Atlas, registry: &BlockRegistry) -> PassMeshes {
+    let mut v = PassMeshes::default();
     
-    let (block_list, other_coord) = match face {
-        /*
-            if let Some(c) = coord.back() {
-                (blocks, Some(c))
-            } else {
-                match adjacent.back {
-                    Some(back) => (back.blocks(), Some(SectorSpaceCoords::new(coord.x(), coord.y(), 0))),
-                    None => (blocks, None),
+    // The visible faces in one layer of the sector.
+    let mut mask = [None; SECTOR_SIZE * SECTOR_SIZE];
+    
+    for &face in &FACES {
+        let (normal_axis, u_axis, v_axis) = face.axes();
+        
+        for layer in 0..SECTOR_SIZE {
+            let coord_at = |a: usize, b: usize| {
+                let mut c = [0; 3];
+                c[normal_axis] = layer as u8;
+                c[u_axis] = a as u8;
+                c[v_axis] = b as u8;
+                
+                SectorSpaceCoords::new(c[0], c[1], c[2])
+            };
+            
+            for b in 0..SECTOR_SIZE {
+                for a in 0..SECTOR_SIZE {
+                    let coord = coord_at(a, b);
+                    let block = blocks.get(coord);
+                    
+                    mask[a + b * SECTOR_SIZE] =
+                        if registry.needs_rendering(*block) &&
+                           registry.shape(*block) == BlockShape::Cube &&
+                           should_create_face(face, coord, blocks, adjacent, registry) {
+                            Some((*block,
+                                  face_occlusion(face, coord, blocks, adjacent, registry),
+                                  face_light(face, coord, blocks, adjacent, light,
+                                             adjacent_light, registry)))
+                        } else {
+                            None
+                        };
                 }
             }
-            */
-        
-        /*
-        Back => {
-            match coord.back() {
-                Some(back) => Some(back),
-                None => {
-                    if let Some(sector) = adjacent.back {
-                        Some(sector.blocks().get(SectorSpaceCoords::new(coord.x(), coord.y(), SECTOR_SIZE as u8 - 1)))
-                    } else {
-                        None
+            
+            for b in 0..SECTOR_SIZE {
+                let mut a = 0;
+                
+                while a < SECTOR_SIZE {
+                    let (block, occlusion, corner_light) = match mask[a + b * SECTOR_SIZE] {
+                        Some(key) => key,
+                        None => {
+                            a += 1;
+                            continue;
+                        },
+                    };
+                    
+                    let key = Some((block, occlusion, corner_light));
+                    let mergeable = occlusion.iter().all(|&o| o == occlusion[0]) &&
+                                    corner_light.iter().all(|&l| l == corner_light[0]);
+                    
+                    // Grow the quad along U, then along V
+                    // for as long as every row matches.
+                    let mut width = 1;
+                    while mergeable && a + width < SECTOR_SIZE &&
+                          mask[a + width + b * SECTOR_SIZE] == key {
+                        width += 1;
+                    }
+                    
+                    let mut height = 1;
+                    while mergeable && b + height < SECTOR_SIZE &&
+                          (a..a + width).all(|i| mask[i + (b + height) * SECTOR_SIZE] == key) {
+                        height += 1;
+                    }
+                    
+                    for j in b..b + height {
+                        for i in a..a + width {
+                            mask[i + j * SECTOR_SIZE] = None;
+                        }
                     }
+                    
+                    let mut extent = UNIT;
+                    extent.max[u_axis] = width as f32;
+                    extent.max[v_axis] = height as f32;
+                    
+                    generate_face(&mut v[registry.pass(block) as usize],
+                                  (coord_at(a, b), &block), face, extent,
+                                  occlusion, corner_light, atlas);
+                    
+                    a += width;
                 }
             }
-        },
-        */
-        //Back => (blocks, coord.back()),
-        
-        /*
+        }
+    }
+    
+    generate_shape_vertices(&mut v, blocks, adjacent, light, adjacent_light, atlas, registry);
+    
+    finish(v)
+}
+
+fn should_create_face(face: Face, coord: SectorSpaceCoords, blocks: &BlockList,
+                      adjacent: &AdjacentSectors, registry: &BlockRegistry) -> bool {
+    use self::Face::*;
+    
+    let (block_list, other_coord) = match face {
         Back =>
             coord.back().map_or_else(|| {
-                adjacent.back.map_or_else(|| (blocks, None), |back| {
-                    (back.blocks(), Some(SectorSpaceCoords::new(coord.x(), coord.y(), 0)))
-                })
+                (adjacent.back, Some(SectorSpaceCoords::new(coord.x(), coord.y(), SECTOR_SIZE as u8 - 1)))
             }, |c| (blocks, Some(c))),
         Front => 
             coord.front().map_or_else(|| {
-                adjacent.front.map_or_else(|| (blocks, None), |front| {
-                    (front.blocks(), Some(SectorSpaceCoords::new(coord.x(), coord.y(), SECTOR_SIZE as u8 - 1)))
-                })
+                (adjacent.front, Some(SectorSpaceCoords::new(coord.x(), coord.y(), 0)))
             }, |c| (blocks, Some(c))),
         Top =>
             coord.top().map_or_else(|| {
-                adjacent.top.map_or_else(|| (blocks, None), |top| {
-                    (top.blocks(), Some(SectorSpaceCoords::new(coord.x(), 0, coord.z())))
-                })
+                (adjacent.top, Some(SectorSpaceCoords::new(coord.x(), 0, coord.z())))
             }, |c| (blocks, Some(c))),
         Bottom =>
             coord.bottom().map_or_else(|| {
-                adjacent.bottom.map_or_else(|| (blocks, None), |bottom| {
-                    (bottom.blocks(), Some(SectorSpaceCoords::new(coord.x(), SECTOR_SIZE as u8 - 1, coord.z())))
-                })
+                (adjacent.bottom, Some(SectorSpaceCoords::new(coord.x(), SECTOR_SIZE as u8 - 1, coord.z())))
             }, |c| (blocks, Some(c))),
         Left =>
             coord.left().map_or_else(|| {
-                adjacent.left.map_or_else(|| (blocks, None), |left| {
-                    (left.blocks(), Some(SectorSpaceCoords::new(SECTOR_SIZE as u8 - 1, coord.y(), coord.z())))
-                })
+                (adjacent.left, Some(SectorSpaceCoords::new(SECTOR_SIZE as u8 - 1, coord.y(), coord.z())))
             }, |c| (blocks, Some(c))),
         Right =>
             coord.right().map_or_else(|| {
-                adjacent.right.map_or_else(|| (blocks, None), |right| {
-                    (right.blocks(), Some(SectorSpaceCoords::new(0, coord.y(), coord.z())))
-                })
+                (adjacent.right, Some(SectorSpaceCoords::new(0, coord.y(), coord.z())))
             }, |c| (blocks, Some(c))),
-        */
+    };
+    
+    // Only a neighbor that covers the whole face can hide it. Blocks
+    // that are not cubes are transparent only around their boxes.
+    // Faces between two blocks of the same transparent kind, such
+    // as water, are hidden, since nothing is seen between them.
+    // Cutout blocks are the exception, as their faces show
+    // through the holes in each other.
+    other_coord.is_none_or(|c| {
+        let other = *block_list.get(c);
+        let block = *blocks.get(coord);
+        let shape = registry.shape(other);
         
-        /*
-        Back => (blocks, coord.back()),
-        Front => (blocks, coord.front()),
-        Top => (blocks, coord.top()),
-        Bottom => (blocks, coord.bottom()),
-        Left => (blocks, coord.left()),
-        Right => (blocks, coord.right()),
-        */
+        if !shape.covers(face.opposite()) {
+            return true;
+        }
         
-        Back =>
-            coord.back().map_or_else(|| {
-                (adjacent.back.blocks(), Some(SectorSpaceCoords::new(coord.x(), coord.y(), SECTOR_SIZE as u8 - 1)))
-            }, |c| (blocks, Some(c))),
-        Front => 
-            coord.front().map_or_else(|| {
-                (adjacent.front.blocks(), Some(SectorSpaceCoords::new(coord.x(), coord.y(), 0)))
-            }, |c| (blocks, Some(c))),
-        Top =>
-            coord.top().map_or_else(|| {
-                (adjacent.top.blocks(), Some(SectorSpaceCoords::new(coord.x(), 0, coord.z())))
-            }, |c| (blocks, Some(c))),
-        Bottom =>
-            coord.bottom().map_or_else(|| {
-                (adjacent.bottom.blocks(), Some(SectorSpaceCoords::new(coord.x(), SECTOR_SIZE as u8 - 1, coord.z())))
-            }, |c| (blocks, Some(c))),
-        Left =>
-            coord.left().map_or_else(|| {
-                (adjacent.left.blocks(), Some(SectorSpaceCoords::new(SECTOR_SIZE as u8 - 1, coord.y(), coord.z())))
-            }, |c| (blocks, Some(c))),
-        Right =>
-            coord.right().map_or_else(|| {
-                (adjacent.right.blocks(), Some(SectorSpaceCoords::new(0, coord.y(), coord.z())))
-            }, |c| (blocks, Some(c))),
+        if shape != BlockShape::Cube && registry.pass(other) == RenderPass::Opaque {
+            return false;
+        }
+        
+        registry.is_transparent(other) &&
+            (other != block || registry.pass(block) == RenderPass::Cutout)
+    })
+}
+
+// The corners of a face, as indices into `POSITIONS`,
+// in the order in which its vertices are emitted.
+fn face_corners(face: Face) -> [usize; 4] {
+    use self::Face::*;
+    
+    //Bottom => [2, 5, 6, 1],
+    
+    match face {
+        Back => [0, 1, 2, 3],
+        Front => [4, 5, 6, 7],
+        Top => [5, 2, 1, 6],
+        Bottom => [3, 4, 7, 0],
+        Left => [7, 6, 1, 0],
+        Right => [3, 2, 5, 4],
+    }
+}
+
+// Find a block in sector coords that may lie one block outside
+// of the sector, in which case the adjacent sector is used.
+// Returns the data of the sector holding the block, and the
+// block's coords in that sector. Blocks outside of more than one
+// side, in the sectors that only share an edge or corner, are
+// not known unless those sectors were added to `adjacent`.
+fn near<'a, T>(pos: [i32; 3], center: &'a T,
+               adjacent: &Adjacent<'a, T>) -> Option<(&'a T, SectorSpaceCoords)> {
+    let size = SECTOR_SIZE as i32;
+    let mut local = pos;
+    let mut offset = [0; 3];
+    
+    for axis in 0..3 {
+        if pos[axis] < 0 {
+            offset[axis] = -1;
+            local[axis] += size;
+        } else if pos[axis] >= size {
+            offset[axis] = 1;
+            local[axis] -= size;
+        }
+    }
+    
+    let sector = match offset {
+        [0, 0, 0] => Some(center),
+        [-1, 0, 0] => Some(adjacent.left),
+        [1, 0, 0] => Some(adjacent.right),
+        [0, -1, 0] => Some(adjacent.bottom),
+        [0, 1, 0] => Some(adjacent.top),
+        [0, 0, -1] => Some(adjacent.back),
+        [0, 0, 1] => Some(adjacent.front),
+        [x, y, z] => adjacent.diagonal((x, y, z)),
     };
     
-    other_coord.map_or(true, |c| !block_list.get(c).needs_rendering())
+    sector.map(|sector| {
+        (sector, SectorSpaceCoords::new(local[0] as u8, local[1] as u8, local[2] as u8))
+    })
 }
 
-fn generate_face(v: &mut Vec<Vertex>, block: (SectorSpaceCoords, &Block),
-                 face: Face, texture_info: &OutputInfo) {
-    use self::Face::*;
+// Look at a block in sector coords that may lie
+// one block outside of the sector, like `near`.
+fn block_near<'a>(pos: [i32; 3], blocks: &'a BlockList,
+                  adjacent: &AdjacentSectors<'a>) -> Option<&'a BlockId> {
+    near(pos, blocks, adjacent).map(|(list, local)| list.get(local))
+}
+
+// The blocks in front of a face that touch each of its corners,
+// in the order of `face_corners`. For every corner, these are the
+// block directly in front of the face, the two beside that block
+// toward the corner, and the one diagonal to it.
+fn corner_samples(face: Face, coord: SectorSpaceCoords) -> [[[i32; 3]; 4]; 4] {
+    let (_, u_axis, v_axis) = face.axes();
+    let normal = face.normal();
+    
+    // The layer of blocks just in front of the face.
+    let front = [coord.x() as i32 + normal.0,
+                 coord.y() as i32 + normal.1,
+                 coord.z() as i32 + normal.2];
     
-    //Bottom => ([2, 5, 6, 1], ([1.0, 1.0], [1.0, 0.0], [0.0, 0.0], [0.0, 1.0])),
+    let mut samples = [[front; 4]; 4];
+    for (i, &corner) in face_corners(face).iter().enumerate() {
+        let corner = POSITIONS[corner];
+        
+        let mut side1 = front;
+        side1[u_axis] += if corner[u_axis] > 0.5 { 1 } else { -1 };
+        
+        let mut side2 = front;
+        side2[v_axis] += if corner[v_axis] > 0.5 { 1 } else { -1 };
+        
+        let mut diagonal = side1;
+        diagonal[v_axis] = side2[v_axis];
+        
+        samples[i] = [front, side1, side2, diagonal];
+    }
     
-    let uvs = tex_coords(block.1, texture_info);
+    samples
+}
+
+// The ambient occlusion at each corner of a block face, in the
+// order of `face_corners`. Each value counts the open blocks of
+// the 3 that touch the corner in front of the face: 3 means the
+// corner is unoccluded, and 0 means it is fully occluded.
+fn face_occlusion(face: Face, coord: SectorSpaceCoords, blocks: &BlockList,
+                  adjacent: &AdjacentSectors, registry: &BlockRegistry) -> [u32; 4] {
+    let solid = |pos: [i32; 3]| {
+        block_near(pos, blocks, adjacent).is_some_and(|b| !registry.is_transparent(*b))
+    };
     
-    let (triangles, uv) = match face {
-        Back => ([0, 1, 2, 3], uvs),
-        Front => ([4, 5, 6, 7], uvs),
-        Top => ([5, 2, 1, 6], uvs),
-        Bottom => ([3, 4, 7, 0], uvs),
-        Left => ([7, 6, 1, 0], uvs),
-        Right => ([3, 2, 5, 4], uvs),
+    let mut occlusion = [0; 4];
+    for (i, samples) in corner_samples(face, coord).iter().enumerate() {
+        let [_, side1, side2, diagonal] = *samples;
+        
+        occlusion[i] = match (solid(side1), solid(side2), solid(diagonal)) {
+            (true, true, _) => 0,
+            (s1, s2, d) => 3 - (s1 as u32 + s2 as u32 + d as u32),
+        };
+    }
+    
+    occlusion
+}
+
+// The light at each corner of a block face, in the order of
+// `face_corners`, with the sky light in bits 4 to 7 and the block
+// light in bits 0 to 3. Each corner averages the light of the
+// open blocks among the 4 that touch it in front of the face,
+// so that the light changes smoothly from block to block.
+fn face_light(face: Face, coord: SectorSpaceCoords, blocks: &BlockList,
+              adjacent: &AdjacentSectors, light: &LightList,
+              adjacent_light: &AdjacentLight, registry: &BlockRegistry) -> [u32; 4] {
+    let open = |pos: [i32; 3]| {
+        block_near(pos, blocks, adjacent).is_some_and(|b| registry.is_transparent(*b))
     };
     
+    let mut result = [0; 4];
+    for (i, samples) in corner_samples(face, coord).iter().enumerate() {
+        let (mut sky, mut glow, mut count) = (0, 0, 0);
+        
+        for (j, &pos) in samples.iter().enumerate() {
+            // The diagonal block cannot be seen
+            // when both of its sides are closed.
+            if j == 3 && !open(samples[1]) && !open(samples[2]) {
+                continue;
+            }
+            
+            if !open(pos) {
+                continue;
+            }
+            
+            if let Some((list, local)) = near(pos, light, adjacent_light) {
+                sky += list.get(local, Channel::Sky) as u32;
+                glow += list.get(local, Channel::Block) as u32;
+                count += 1;
+            }
+        }
+        
+        // A corner that sees no open block stays dark.
+        if count == 0 {
+            continue;
+        }
+        
+        let (sky, glow) = ((sky + count / 2) / count, (glow + count / 2) / count);
+        result[i] = sky << 4 | glow;
+    }
+    
+    result
+}
+
+// Add the faces of the blocks that are not cubes. The sides of
+// their boxes that lie on the sides of the block are hidden by
+// neighbors just like the faces of a cube. The other sides are
+// always drawn, unless another box of the shape lies against them,
+// and take the light of the block itself.
+fn generate_shape_vertices(v: &mut PassMeshes, blocks: &BlockList,
+                           adjacent: &AdjacentSectors, light: &LightList,
+                           adjacent_light: &AdjacentLight, atlas: &Atlas,
+                           registry: &BlockRegistry) {
+    for i in blocks {
+        let (coord, block) = i;
+        let shape = registry.shape(*block);
+        
+        if !registry.needs_rendering(*block) || shape == BlockShape::Cube {
+            continue;
+        }
+        
+        let v = &mut v[registry.pass(*block) as usize];
+        let own_light = (light.get(coord, Channel::Sky) as u32) << 4 |
+                        light.get(coord, Channel::Block) as u32;
+        
+        if shape == BlockShape::Cross {
+            generate_cross(v, i, own_light, atlas);
+            continue;
+        }
+        
+        let boxes = shape.boxes();
+        
+        for (index, b) in boxes.iter().enumerate() {
+            for &face in &FACES {
+                let (occlusion, corner_light) = if on_block_side(b, face) {
+                    if !should_create_face(face, coord, blocks, adjacent, registry) {
+                        continue;
+                    }
+                    
+                    (face_occlusion(face, coord, blocks, adjacent, registry),
+                     face_light(face, coord, blocks, adjacent, light, adjacent_light, registry))
+                } else {
+                    let hidden = boxes.iter().enumerate().any(|(j, other)| {
+                        j != index && box_hides(other, b, face)
+                    });
+                    
+                    if hidden {
+                        continue;
+                    }
+                    
+                    ([3; 4], [own_light; 4])
+                };
+                
+                generate_face(v, i, face, *b, occlusion, corner_light, atlas);
+            }
+        }
+    }
+}
+
+// Add two quads crossed along the diagonals of a block, which
+// are seen from both sides, with the tile of its front face.
+fn generate_cross(v: &mut Mesh, block: (SectorSpaceCoords, &BlockId),
+                  light: u32, atlas: &Atlas) {
+    let tile = tile_index(block.1, Face::Front, atlas);
     let original = ((block.0).x() as f32, (block.0).y() as f32, (block.0).z() as f32);
     
-    let mut vtx0 = (POSITIONS[triangles[0]], uv.0, face as u32);
-    vtx0.0[0] += original.0;
-    vtx0.0[1] += original.1;
-    vtx0.0[2] += original.2;
-    
-    let mut vtx1 = (POSITIONS[triangles[1]], uv.1, face as u32);
-    vtx1.0[0] += original.0;
-    vtx1.0[1] += original.1;
-    vtx1.0[2] += original.2;
-    
-    let mut vtx2 = (POSITIONS[triangles[2]], uv.2, face as u32);
-    vtx2.0[0] += original.0;
-    vtx2.0[1] += original.1;
-    vtx2.0[2] += original.2;
-    
-    let mut vtx3 = (POSITIONS[triangles[3]], uv.3, face as u32);
-    vtx3.0[0] += original.0;
-    vtx3.0[1] += original.1;
-    vtx3.0[2] += original.2;
-    
-    // Add to mesh
-    v.push(vtx0);
-    v.push(vtx1);
-    v.push(vtx2);
-    
-    v.push(vtx0);
-    v.push(vtx2);
-    v.push(vtx3);
+    // The ends of each quad, along x and z.
+    for &(start, end) in &[([0., 0.], [1., 1.]), ([1., 0.], [0., 1.])] {
+        let corner = |xz: [f32; 2], y: f32| {
+            VertexData {
+                pos: [original.0 + xz[0], original.1 + y, original.2 + xz[1]],
+                face: CROSS_FACE,
+                occlusion: 3,
+                light,
+                tile,
+            }.pack()
+        };
+        
+        let quad = [corner(start, 0.),
+                    corner(start, 1.),
+                    corner(end, 1.),
+                    corner(end, 0.)];
+        
+        v.push_quad(quad, [3; 4]);
+        v.push_quad([quad[3], quad[2], quad[1], quad[0]], [3; 4]);
+    }
 }
 
-fn tex_coords(block: &Block, texture_info: &OutputInfo) -> (UV, UV, UV, UV) {
-    let (width, height) = (texture_info.width as f32,
-                           texture_info.height as f32);
+// Determine if a side of a box lies on the side of its block.
+fn on_block_side(b: &BlockBox, face: Face) -> bool {
+    let (axis, _, _) = face.axes();
+    
+    if faces_positive(face) {
+        b.max[axis] >= 1.
+    } else {
+        b.min[axis] <= 0.
+    }
+}
+
+// Determine if `other` lies against the side of `b` on `face`,
+// and covers the whole of it.
+fn box_hides(other: &BlockBox, b: &BlockBox, face: Face) -> bool {
+    let (axis, u_axis, v_axis) = face.axes();
+    
+    let touches = if faces_positive(face) {
+        other.min[axis] == b.max[axis]
+    } else {
+        other.max[axis] == b.min[axis]
+    };
+    
+    touches && [u_axis, v_axis].iter().all(|&a| other.min[a] <= b.min[a] &&
+                                                 other.max[a] >= b.max[a])
+}
+
+// Determine if a face points toward the positive end of its axis.
+fn faces_positive(face: Face) -> bool {
+    let normal = face.normal();
+    
+    normal.0 + normal.1 + normal.2 > 0
+}
+
+// Add a quad to the mesh, on the side `face` of the box `extent`,
+// which is in blocks and starts inside the block. `occlusion` and
+// `light` hold the ambient occlusion and the light of each corner.
+fn generate_face(v: &mut Mesh, block: (SectorSpaceCoords, &BlockId),
+                 face: Face, extent: BlockBox, occlusion: [u32; 4],
+                 light: [u32; 4], atlas: &Atlas) {
+    let tile = tile_index(block.1, face, atlas);
+    let triangles = face_corners(face);
+    
+    let offset = extent.min;
+    let size = [extent.max[0] - offset[0], extent.max[1] - offset[1], extent.max[2] - offset[2]];
+    let original = ((block.0).x() as f32 + offset[0],
+                    (block.0).y() as f32 + offset[1],
+                    (block.0).z() as f32 + offset[2]);
+    
+    let corner = |index: usize| {
+        let pos = POSITIONS[triangles[index]];
+        
+        VertexData {
+            pos: [original.0 + pos[0] * size[0],
+                  original.1 + pos[1] * size[1],
+                  original.2 + pos[2] * size[2]],
+            face: face as u32,
+            occlusion: occlusion[index],
+            light: light[index],
+            tile,
+        }.pack()
+    };
+    
+    v.push_quad([corner(0), corner(1), corner(2), corner(3)], occlusion);
+}
+
+// The index of the atlas tile for one face of a block.
+fn tile_index(block: &BlockId, face: Face, atlas: &Atlas) -> u32 {
+    atlas.tile_index(*block, face).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use png::Decoder;
+    use terrain::{VertexData, CROSS_FACE, POSITION_STEPS};
+    use super::*;
+    
+    fn registry() -> BlockRegistry {
+        BlockRegistry::load(File::open("data/blocks.toml").unwrap()).unwrap()
+    }
+    
+    fn atlas(registry: &BlockRegistry) -> Atlas {
+        let (info, _) = Decoder::new(File::open("data/atlas.png").unwrap()).read_info().unwrap();
+        
+        Atlas::load(File::open("data/atlas.txt").unwrap(), &info, registry).unwrap()
+    }
+    
+    // Mesh a sector surrounded by air, in darkness.
+    fn mesh(mode: MeshMode, blocks: &BlockList) -> PassMeshes {
+        mesh_with_diagonals(mode, blocks, &[])
+    }
+    
+    // Mesh a sector surrounded by air, in darkness, except for
+    // the given neighbors that share an edge or a corner.
+    fn mesh_with_diagonals(mode: MeshMode, blocks: &BlockList,
+                           diagonals: &[((i32, i32, i32), BlockList)]) -> PassMeshes {
+        let registry = registry();
+        let atlas = atlas(&registry);
+        let air = BlockList::new_air();
+        let dark = LightList::dark();
+        let mut adjacent = AdjacentSectors::new(&air, &air, &air, &air, &air, &air);
+        let adjacent_light = AdjacentLight::new(&dark, &dark, &dark, &dark, &dark, &dark);
+        for &(offset, ref diagonal) in diagonals {
+            adjacent.set_diagonal(offset, diagonal);
+        }
+        
+        generate_vertices(mode, blocks, &adjacent, &dark, &adjacent_light, &atlas, &registry)
+    }
+    
+    fn quads(meshes: &PassMeshes) -> usize {
+        meshes.iter().map(|mesh| mesh.indices.len() / 6).sum()
+    }
+    
+    // The vertices of each triangle, three in a row,
+    // as they were stored before meshes were indexed.
+    fn triangles(mesh: &Mesh) -> Vec<Vertex> {
+        mesh.indices.iter().map(|&i| mesh.vertices[i as usize]).collect()
+    }
+    
+    // The area covered by the triangles of each face.
+    fn face_areas(meshes: &PassMeshes) -> [f32; 6] {
+        let mut areas = [0.; 6];
+        
+        for mesh in meshes {
+            for triangle in triangles(mesh).chunks(3) {
+                let corner = |i: usize| VertexData::unpack(triangle[i]);
+                let (a, b, c) = (corner(0).pos, corner(1).pos, corner(2).pos);
+                let u = [b[0] - a[0], b[1] - a[1], b[2] - a[2]];
+                let v = [c[0] - a[0], c[1] - a[1], c[2] - a[2]];
+                let cross = [u[1] * v[2] - u[2] * v[1],
+                             u[2] * v[0] - u[0] * v[2],
+                             u[0] * v[1] - u[1] * v[0]];
+                
+                areas[corner(0).face as usize] += (cross[0] * cross[0] +
+                                                   cross[1] * cross[1] +
+                                                   cross[2] * cross[2]).sqrt() / 2.;
+            }
+        }
+        
+        areas
+    }
     
-    let (ru, rv) = (TILE_SIZE / width,
-                    TILE_SIZE / height);
+    // The six vertices of a quad, as `push_quad` stored
+    // them before meshes were indexed.
+    fn six_vertices(corners: [Vertex; 4], occlusion: [u32; 4]) -> Vec<Vertex> {
+        let [vtx0, vtx1, vtx2, vtx3] = corners;
+        
+        if occlusion[0] + occlusion[2] >= occlusion[1] + occlusion[3] {
+            vec![vtx0, vtx1, vtx2, vtx0, vtx2, vtx3]
+        } else {
+            vec![vtx1, vtx2, vtx3, vtx1, vtx3, vtx0]
+        }
+    }
     
-    let num = *block as u32 as f32 - 1.;
+    // The corners of the top face of the block at `x`, with
+    // the occlusion of each corner.
+    fn top_corners(x: f32, occlusion: [u32; 4]) -> [Vertex; 4] {
+        let corner = |i: usize, dx: f32, dz: f32| VertexData {
+            pos: [x + dx, 1., dz],
+            face: Face::Top as u32,
+            occlusion: occlusion[i],
+            light: 0,
+            tile: 0,
+        }.pack();
+        
+        [corner(0, 0., 0.), corner(1, 0., 1.), corner(2, 1., 1.), corner(3, 1., 0.)]
+    }
     
-    (
-        [ru * (num + 1.), rv],
-        [ru * (num + 1.), 0.],
-        [ru *  num,       0.],
-        [ru *  num,       rv],
-    )
+    // A sector filled with limestone where `filled` holds.
+    fn limestone<F: Fn(u8, u8, u8) -> bool>(filled: F) -> BlockList {
+        let limestone = registry().id("limestone").unwrap();
+        let mut blocks = BlockList::new_air();
+        
+        for z in 0..SECTOR_SIZE as u8 {
+            for y in 0..SECTOR_SIZE as u8 {
+                for x in 0..SECTOR_SIZE as u8 {
+                    if filled(x, y, z) {
+                        blocks.set(SectorSpaceCoords::new(x, y, z), limestone);
+                    }
+                }
+            }
+        }
+        
+        blocks
+    }
+    
+    #[test]
+    fn water_hides_only_water() {
+        // Two blocks of water, and limestone beside them along x.
+        let registry = registry();
+        let water = registry.id("water").unwrap();
+        let mut blocks = limestone(|x, y, z| (x, y, z) == (7, 5, 5));
+        blocks.set(SectorSpaceCoords::new(5, 5, 5), water);
+        blocks.set(SectorSpaceCoords::new(6, 5, 5), water);
+        
+        let meshes = mesh(MeshMode::Simple, &blocks);
+        let areas = face_areas(&meshes);
+        
+        // The faces between the blocks of water are hidden, and so
+        // is the face of the water that touches the limestone. The
+        // face of the limestone is seen through the water.
+        assert_eq!(meshes[RenderPass::Translucent as usize].indices.len() / 6, 9);
+        assert_eq!(meshes[RenderPass::Opaque as usize].indices.len() / 6, 6);
+        assert_eq!(areas[Face::Left as usize], 2.);
+        assert_eq!(areas[Face::Right as usize], 1.);
+    }
+    
+    #[test]
+    fn greedy_merges_flat_slab() {
+        let blocks = limestone(|_, y, _| y < 10);
+        let simple = mesh(MeshMode::Simple, &blocks);
+        let greedy = mesh(MeshMode::Greedy, &blocks);
+        
+        // Every block on the top and bottom, and 10 rows
+        // of blocks on each of the 4 sides.
+        assert_eq!(quads(&simple), 2 * 32 * 32 + 4 * 32 * 10);
+        
+        // One quad for each side of the slab.
+        assert_eq!(quads(&greedy), 6);
+        assert_eq!(face_areas(&simple), face_areas(&greedy));
+    }
+    
+    #[test]
+    fn greedy_keeps_checkerboard() {
+        // No two visible faces of a checkerboard are next
+        // to each other, so there is nothing to merge.
+        let blocks = limestone(|x, y, z| (x + y + z) % 2 == 0);
+        let simple = mesh(MeshMode::Simple, &blocks);
+        let greedy = mesh(MeshMode::Greedy, &blocks);
+        
+        assert_eq!(quads(&simple), 6 * 32 * 32 * 32 / 2);
+        assert_eq!(quads(&greedy), quads(&simple));
+        assert_eq!(face_areas(&simple), face_areas(&greedy));
+    }
+    
+    #[test]
+    fn indices_match_six_vertices() {
+        // Quads brighter at corners 0 and 2 are split along the
+        // diagonal between them, the others along the other one.
+        let occlusions = [[3, 3, 3, 3], [3, 0, 3, 0], [1, 3, 3, 0],
+                          [0, 3, 0, 3], [3, 1, 0, 3]];
+        
+        let mut mesh = Mesh::default();
+        let mut expected = Vec::new();
+        for (x, &occlusion) in occlusions.iter().enumerate() {
+            let corners = top_corners(x as f32 * 2., occlusion);
+            mesh.push_quad(corners, occlusion);
+            expected.extend(six_vertices(corners, occlusion));
+        }
+        
+        assert_eq!(mesh.vertices.len(), 20);
+        assert_eq!(triangles(&mesh), expected);
+    }
+    
+    #[test]
+    fn quads_share_corners() {
+        // Three quads in a row, meeting along two edges.
+        let mut mesh = Mesh::default();
+        let mut expected = Vec::new();
+        for &(x, occlusion) in &[(0., [3; 4]), (1., [3; 4]), (2., [3; 4])] {
+            let corners = top_corners(x, occlusion);
+            mesh.push_quad(corners, occlusion);
+            expected.extend(six_vertices(corners, occlusion));
+        }
+        
+        assert_eq!(mesh.vertices.len(), 8);
+        assert_eq!(triangles(&mesh), expected);
+        
+        // Corners with different occlusion are not shared.
+        let occlusion = [0, 0, 3, 3];
+        let corners = top_corners(3., occlusion);
+        mesh.push_quad(corners, occlusion);
+        expected.extend(six_vertices(corners, occlusion));
+        
+        assert_eq!(mesh.vertices.len(), 12);
+        assert_eq!(triangles(&mesh), expected);
+    }
+    
+    #[test]
+    fn packed_vertices_round_trip() {
+        let shapes = [BlockShape::Cube, BlockShape::Slab, BlockShape::Stairs, BlockShape::Fence];
+        let blocks = [[0., 0., 0.], [31., 31., 31.], [7., 18., 30.]];
+        
+        for (n, block) in blocks.iter().enumerate() {
+            for b in shapes.iter().flat_map(|shape| shape.boxes()) {
+                for &face in &FACES {
+                    for (i, &c) in face_corners(face).iter().enumerate() {
+                        let mut pos = [0.; 3];
+                        for axis in 0..3 {
+                            pos[axis] = block[axis] + b.min[axis] +
+                                        POSITIONS[c][axis] * (b.max[axis] - b.min[axis]);
+                        }
+                        
+                        let vertex = VertexData {
+                            pos,
+                            face: face as u32,
+                            occlusion: i as u32,
+                            light: [0, 0x5a, 0xff][n],
+                            tile: [0, 1234, 0xffff][n],
+                        };
+                        
+                        assert_eq!(VertexData::unpack(vertex.pack()), vertex);
+                    }
+                }
+            }
+            
+            let cross = VertexData {
+                pos: [block[0] + 1., block[1], block[2]],
+                face: CROSS_FACE,
+                occlusion: 3,
+                light: 0xff,
+                tile: n as u32,
+            };
+            
+            assert_eq!(VertexData::unpack(cross.pack()), cross);
+        }
+        
+        // Positions are rounded to the nearest step.
+        let vertex = VertexData {
+            pos: [1. + 0.4 / POSITION_STEPS, 2. - 0.4 / POSITION_STEPS, 0.],
+            face: 0,
+            occlusion: 0,
+            light: 0,
+            tile: 0,
+        };
+        
+        assert_eq!(VertexData::unpack(vertex.pack()).pos, [1., 2., 0.]);
+    }
+    
+    #[test]
+    fn occlusion_reaches_diagonal_sectors() {
+        // A block on the top right edge of the sector, and a row of
+        // blocks along the same edge in the sector above and to the
+        // right, which shades the right side of its top face.
+        let blocks = limestone(|x, y, z| x == 31 && y == 31 && z == 15);
+        let edge = limestone(|x, y, _| x == 0 && y == 0);
+        
+        let top_occlusion = |meshes: &PassMeshes| {
+            let mut occlusion: Vec<_> = meshes.iter().flat_map(triangles)
+                                              .map(VertexData::unpack)
+                                              .filter(|v| v.face == Face::Top as u32)
+                                              .map(|v| (v.pos[0] as u32, v.occlusion))
+                                              .collect();
+            occlusion.sort();
+            occlusion.dedup();
+            occlusion
+        };
+        
+        let alone = mesh(MeshMode::Simple, &blocks);
+        assert_eq!(top_occlusion(&alone), vec![(31, 3), (32, 3)]);
+        
+        let shaded = mesh_with_diagonals(MeshMode::Simple, &blocks, &[((1, 1, 0), edge)]);
+        assert_eq!(top_occlusion(&shaded), vec![(31, 3), (32, 1)]);
+    }
 }
@@ -1,15 +1,29 @@
 //! Module related to managing, drawing, and colliding with terrain.
 
+mod atlas;
+mod biome;
+mod gen_config;
+mod heightmap;
+mod light;
+mod map;
 mod mesh_gen;
+mod raycast;
+mod region;
+mod registry;
+mod shape;
+mod simple_gen;
+mod vertex;
 mod voxel;
 mod world_gen;
 
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::mem;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use std::sync::mpsc::{self, Receiver, Sender};
 use std::thread;
 use std::time::{Duration, Instant};
+use luminance::blending::{Equation, Factor};
 use luminance::framebuffer::Framebuffer;
 use luminance::linear::M44;
 use luminance::pipeline::{entry, pipeline, RenderState};
@@ -22,22 +36,42 @@ use maths::{Frustum, ToMatrix, Translation};
 use model::Drawable;
 use resources::Resources;
 use shader;
-use self::voxel::{AdjacentSectors, Block, BlockList, Sector, SectorSpaceCoords};
-use self::world_gen::WorldGen;
+use self::light::{LightList, NeighborLight};
+use self::mesh_gen::FACES;
+use self::region::RegionStore;
+use self::voxel::{AdjacentLight, AdjacentSectors, BlockList, Sector, SectorSpaceCoords,
+                  DIAGONALS};
 
-// Type of terrain position vertex attribute.
+pub use self::atlas::Atlas;
+pub use self::biome::Biome;
+pub use self::heightmap::{Edges, Heightmap, HeightmapOptions, HeightmapWorld};
+pub use self::map::{BiomeMap, BlockColors, MapArea, SurfaceMap};
+pub use self::mesh_gen::{Face, MeshMode};
+pub use self::raycast::{RayHit, Raycast};
+pub use self::registry::{BlockInfo, BlockRegistry, RenderPass, RENDER_PASSES};
+pub use self::shape::{BlockBox, BlockShape};
+pub use self::vertex::{VertexData, CROSS_FACE, POSITION_STEPS};
+pub use self::voxel::BlockId;
+pub use self::simple_gen::{DebugWorld, Superflat};
+pub use self::gen_config::{BiomeConfig, BiomesConfig, CaveConfig, ClimateConfig, OreConfig,
+                           TerrainConfig, TreeConfig, WaterConfig, WorldGenConfig};
+pub use self::world_gen::{WorldGen, WorldGenerator};
+
+// Type of a position in the mesh of a sector.
 type Position = [f32; 3];
 
-// Type of terrain texture coordinate attribute.
-type UV = [f32; 2];
+// Type of the packed position attribute.
+type PackedPosition = u32;
+
+// Type of the attribute that packs the face,
+// ambient occlusion, light, and atlas tile.
+type PackedAttributes = u32;
 
-// Type of face attribute. Serves to replace the normal
-// vector, since on a cube the normals always lie along
-// an axis.
-type FaceNum = u32;
+// A terrain vertex, packed as described in `vertex`.
+type Vertex = (PackedPosition, PackedAttributes);
 
-// A terrain vertex.
-type Vertex = (Position, UV, FaceNum);
+// The shader program that draws the terrain.
+type TerrainProgram = Program<Vertex, (), Uniforms>;
 
 /// The length of one side of a cubic sector.
 pub const SECTOR_SIZE: usize = 32;
@@ -48,55 +82,72 @@ const COLLIDE_PADDING: f32 = 0.3;
 /// Drawable manager for world terrain. Handles the rendering
 /// of each sector.
 pub struct Terrain<'a> {
-    shader: Program<Vertex, (), Uniforms>,
+    shader: TerrainProgram,
     resources: &'a Resources,
     sectors: HashMap<(i32, i32, i32), Sector>,
     shared_info: SharedInfo,
+    store: SharedStore,
+    pending_saves: PendingSaves,
     nearby_rx: Receiver<Nearby>,
     needed_tx: Sender<(i32, i32, i32)>,
+    jobs: JobQueue,
+    gen: SharedGenerator,
+    mesh_mode: MeshMode,
 }
 
 impl<'a> Terrain<'a> {
     /// Create a new `Terrain` using the shared `Resources`.
+    /// The world is generated by `gen`, and sectors are
+    /// saved in region files in `save_dir`.
     /// # Panics
     /// This constructor panics if shaders fail to load.
-    pub fn new(resources: &'a Resources) -> Terrain<'a> {
+    pub fn new(resources: &'a Resources, gen: Arc<dyn WorldGenerator>,
+               save_dir: &Path) -> Terrain<'a> {
         let (shader, warnings) = Self::load_shaders().unwrap();
         for warn in &warnings {
             eprintln!("{:?}", warn);
         }
         
         let shared_info = Arc::new(Mutex::new(Default::default()));
+        let store = Arc::new(Mutex::new(RegionStore::new(save_dir)));
+        let pending_saves = Arc::new(Mutex::new(HashMap::new()));
         
         let sectors = HashMap::with_capacity(5 * 5 * 5);
-        //for dx in -2..3 {
-        //    for dy in -2..3 {                
-        //        for dz in -2..3 {
-        //            let pos = (dx, dy, dz);
-        //            
-        //            sectors.insert(pos, Sector::new(resources, pos));
-        //            
-        //            //println!("pos: {:?}", pos);
-        //        }
-        //    }
-        //}
-        
-        //sectors.insert((0, 0, 0), Sector::new(resources, (0, 0, 0), BlockList::new([Block::Loam; SECTOR_SIZE * SECTOR_SIZE * SECTOR_SIZE])));
-        //sectors.insert((1, 0, 0), Sector::new(resources, (1, 0, 0), BlockList::new([Block::Loam; SECTOR_SIZE * SECTOR_SIZE * SECTOR_SIZE])));
-        //sectors.insert((0, 0, 1), Sector::new(resources, (0, 0, 1), BlockList::new([Block::Loam; SECTOR_SIZE * SECTOR_SIZE * SECTOR_SIZE])));
-        //sectors.insert((1, 0, 1), Sector::new(resources, (1, 0, 1), BlockList::new([Block::Loam; SECTOR_SIZE * SECTOR_SIZE * SECTOR_SIZE])));
         
         let (nearby_tx, nearby_rx) = mpsc::channel();
         let (needed_tx, needed_rx) = mpsc::channel();
-        TerrainGenThread::new(shared_info.clone(), nearby_tx, needed_rx).spawn();
+        let jobs = Arc::new(Mutex::new(VecDeque::new()));
+        TerrainGenThread::new(shared_info.clone(), store.clone(), pending_saves.clone(),
+                              jobs.clone(), nearby_tx, needed_rx, gen.clone()).spawn();
         
         Terrain {
             resources,
             sectors,
             shader,
             shared_info,
+            store,
+            pending_saves,
             nearby_rx,
             needed_tx,
+            jobs,
+            gen,
+            mesh_mode: MeshMode::Greedy,
+        }
+    }
+    
+    /// Choose how sector meshes are built.
+    /// Every sector with a model is rebuilt.
+    pub fn set_mesh_mode(&mut self, mode: MeshMode) {
+        if self.mesh_mode == mode {
+            return;
+        }
+        
+        self.mesh_mode = mode;
+        
+        for sector in self.sectors.values_mut() {
+            if sector.has_models() {
+                sector.mark_dirty();
+            }
         }
     }
     
@@ -118,68 +169,38 @@ impl<'a> Terrain<'a> {
                             break;
                         }
                         
-                        let model;
                         {
                             let sector = self.sectors.get(&sector_coords).unwrap();
-                            if !sector.blocks().needs_rendering() || sector.model().is_some() {
+                            if !sector.blocks().needs_rendering(self.resources.block_registry()) ||
+                               sector.has_models() || sector.is_meshing() {
                                 //println!("bail2");
                                 break;
                             }
-                            
-                            //println!("sector_coords: {:?}", sector_coords);
-                            
-                            let back   = (sector_coords.0,     sector_coords.1,     sector_coords.2 - 1);
-                            let front  = (sector_coords.0,     sector_coords.1,     sector_coords.2 + 1);
-                            let top    = (sector_coords.0,     sector_coords.1 + 1, sector_coords.2    );
-                            let bottom = (sector_coords.0,     sector_coords.1 - 1, sector_coords.2    );
-                            let left   = (sector_coords.0 - 1, sector_coords.1,     sector_coords.2    );
-                            let right  = (sector_coords.0 + 1, sector_coords.1,     sector_coords.2    );
-                            
-                            let back = self.sectors.get(&back);
-                            if back.is_none() {
-                                break;
-                            }
-                            
-                            let front = self.sectors.get(&front);
-                            if front.is_none() {
-                                break;
-                            }
-                            
-                            let top = self.sectors.get(&top);
-                            if top.is_none() {
-                                break;
-                            }
-                            
-                            let bottom = self.sectors.get(&bottom);
-                            if bottom.is_none() {
-                                break;
-                            }
-                            
-                            let left = self.sectors.get(&left);
-                            if left.is_none() {
-                                break;
-                            }
-                            
-                            let right = self.sectors.get(&right);
-                            if right.is_none() {
-                                break;
-                            }
-                            
-                            let adjacent = AdjacentSectors::new(back.unwrap(), front.unwrap(),
-                                                                top.unwrap(), bottom.unwrap(),
-                                                                left.unwrap(), right.unwrap());
-                                
-                            model = sector.create_model(self.resources, sector_coords, &adjacent);
                         }
                         
-                        let sector = self.sectors.get_mut(&sector_coords).unwrap();
-                        sector.set_model(model);
+                        //println!("sector_coords: {:?}", sector_coords);
+                        
+                        self.queue_mesh(sector_coords);
                     } else {
                         self.needed_tx.send(sector_coords).unwrap();
                     }
                 },
-                Nearby::Generated(sector_coords, block_list) => {
-                    self.sectors.entry(sector_coords).or_insert_with(|| Sector::new(block_list));
+                Nearby::Generated { sector, blocks, open_sky } => {
+                    self.queue_light(sector, blocks, open_sky);
+                },
+                Nearby::Lit { job, light } => {
+                    self.add_sector(job, light);
+                },
+                Nearby::Meshed { sector: sector_coords, revision, meshes } => {
+                    // Meshes of blocks that have changed since
+                    // the job was queued are thrown away.
+                    if let Some(sector) = self.sectors.get_mut(&sector_coords) {
+                        if sector.revision() == revision {
+                            let models = sector.create_models(self.resources, sector_coords,
+                                                              &meshes);
+                            sector.set_models(Some(models));
+                        }
+                    }
                 },
             }
             //println!("nearby: {:?}", sector);
@@ -196,8 +217,21 @@ impl<'a> Terrain<'a> {
         }
         //println!("time: {:?}", Instant::now() - begin);
         
+        // Rebuild the models of sectors whose blocks changed.
+        let dirty: Vec<_> = self.sectors.iter()
+                                        .filter(|&(_, sector)| sector.is_dirty() &&
+                                                               !sector.is_meshing())
+                                        .map(|(&coords, _)| coords)
+                                        .collect();
+        for coords in dirty {
+            self.rebuild_model(coords);
+        }
+        
+        // Sectors that are unloaded are saved by the workers.
         let sector = sector_at(&translation);
-        self.sectors.retain(|&k, _| {
+        let pending_saves = &self.pending_saves;
+        let jobs = &self.jobs;
+        self.sectors.retain(|&k, v| {
             let dx = k.0 as f32 - sector.0 as f32;
             let dy = k.1 as f32 - sector.1 as f32;
             let dz = k.2 as f32 - sector.2 as f32;
@@ -206,129 +240,318 @@ impl<'a> Terrain<'a> {
             
             //println!("{}", dist_sq);
             
-            dist_sq < 280.
+            let keep = dist_sq < 280.;
+            if !keep && !v.is_saved() {
+                pending_saves.lock().unwrap().insert(k, v.snapshot());
+                jobs.lock().unwrap().push_back(Job::Save(k));
+            }
+            
+            keep
         });
     }
     
-    /// Adjust for collisions with the terrain.
-    pub fn collide(&self, translation: &mut Translation) {
-        {
-            let back_t = Translation::new(translation.x, translation.y, translation.z.round() - 1.);
-            let back = match self.get_visible_block(&back_t) {
-                Some(b) => !b.is_air(),
-                None => false,
-            };
-            
-            let margin = back_t.z + 1. + COLLIDE_PADDING;
-            if back && translation.z < margin {
-                translation.z = margin;
-            }
+    /// The height of the generated ground at **world** block
+    /// coords, which is the y coordinate of the lowest air block
+    /// above it. Blocks placed or removed since are not counted.
+    pub fn ground_height(&self, x: i32, z: i32) -> i32 {
+        self.gen.height_at(x, z)
+    }
+    
+    /// Get the block at this position in **world** block coords.
+    /// Returns `None` if the sector containing it is not loaded.
+    pub fn get_block(&self, pos: (i32, i32, i32)) -> Option<&BlockId> {
+        let (sector_pos, local) = split_world_coords(pos);
+        
+        self.sectors.get(&sector_pos).map(|sector| sector.blocks().get(local))
+    }
+    
+    /// Set the block at this position in **world** block coords.
+    /// The sector is rebuilt during the next `update`, along with
+    /// any neighboring sector that shares a face, an edge, or a
+    /// corner with the changed block.
+    /// Returns `false` if the sector containing it is not loaded.
+    pub fn set_block(&mut self, pos: (i32, i32, i32), block: BlockId) -> bool {
+        let (sector_pos, local) = split_world_coords(pos);
+        
+        match self.sectors.get_mut(&sector_pos) {
+            Some(sector) => sector.set_block(local, block),
+            None => return false,
         }
         
-        //
+        let changed = light::light_block(&mut self.sectors, self.resources.block_registry(), pos);
+        self.invalidate_models(changed);
         
-        {
-            let front_t = Translation::new(translation.x, translation.y, translation.z.round() + 1.);
-            let front = match self.get_visible_block(&front_t) {
-                Some(f) => !f.is_air(),
-                None => false,
-            };
-            
-            let margin = front_t.z - 1. - COLLIDE_PADDING;
-            if front && translation.z > margin {
-                translation.z = margin;
+        // The neighbors that share the face, edge, or corner
+        // of the sector that the block lies on.
+        let max = SECTOR_SIZE as u8 - 1;
+        let side = |c: u8| if c == 0 { -1 } else if c == max { 1 } else { 0 };
+        let (sx, sy, sz) = (side(local.x()), side(local.y()), side(local.z()));
+        let (x, y, z) = sector_pos;
+        let mut neighbors = HashSet::new();
+        
+        for &dx in &[0, sx] {
+            for &dy in &[0, sy] {
+                for &dz in &[0, sz] {
+                    neighbors.insert((x + dx, y + dy, z + dz));
+                }
             }
         }
+        neighbors.remove(&sector_pos);
         
-        //
+        self.invalidate_models(neighbors);
         
-        {
-            let above_t = Translation::new(translation.x, translation.y.round() + 1., translation.z);
-            let above = match self.get_visible_block(&above_t) {
-                Some(a) => !a.is_air(),
-                None => false,
-            };
-            
-            let margin = above_t.y - 1. - COLLIDE_PADDING;
-            if above && translation.y > margin {
-                translation.y = margin;
-            }
+        true
+    }
+    
+    /// Find the block that the camera is looking at,
+    /// up to `max_distance` away. The ray passes through
+    /// air and fluids, such as water, but stops at every
+    /// other block, solid or not, such as plants.
+    pub fn raycast(&self, camera: &Camera, max_distance: f32) -> Raycast {
+        let pos = camera.translation();
+        let registry = self.resources.block_registry();
+        
+        raycast::cast((pos.x, pos.y, pos.z), camera.direction(), max_distance, |block_pos| {
+            self.get_block(block_pos).map(|&block| {
+                if registry.is_fluid(block) {
+                    BlockId::AIR
+                } else {
+                    block
+                }
+            })
+        })
+    }
+    
+    /// Save every loaded sector that has changed since
+    /// it was last written to disk, and every unloaded
+    /// sector that the workers have yet to save.
+    pub fn save(&mut self) {
+        for (&coords, sector) in &mut self.sectors {
+            save_sector(&self.store, coords, sector);
+        }
+        
+        let pending: Vec<_> = self.pending_saves.lock().unwrap().keys().cloned().collect();
+        for coords in pending {
+            save_unloaded(&self.store, &self.pending_saves, coords);
         }
+    }
+    
+    /// Adjust for collisions with the terrain. The player is a cube
+    /// around `translation`, which is pushed out of every box of the
+    /// solid blocks that it overlaps, along the axis on which the
+    /// overlap is smallest.
+    pub fn collide(&self, translation: &mut Translation) {
+        let registry = self.resources.block_registry();
         
-        //
+        // The blocks that the player may overlap.
+        let low = |p: f32| (p - COLLIDE_PADDING).floor() as i32;
+        let high = |p: f32| (p + COLLIDE_PADDING).floor() as i32;
+        let (min_x, max_x) = (low(translation.x), high(translation.x));
+        let (min_y, max_y) = (low(translation.y), high(translation.y));
+        let (min_z, max_z) = (low(translation.z), high(translation.z));
         
-        {
-            let below_t = Translation::new(translation.x, translation.y.round() - 1., translation.z);
-            let below = match self.get_visible_block(&below_t) {
-                Some(b) => !b.is_air(),
-                None => false,
-            };
-            
-            let margin = below_t.y + 1. + COLLIDE_PADDING;
-            if below && translation.y < margin {
-                translation.y = margin;
+        for y in min_y..max_y + 1 {
+            for z in min_z..max_z + 1 {
+                for x in min_x..max_x + 1 {
+                    let block = match self.get_visible_block((x, y, z)) {
+                        Some(&block) if registry.is_solid(block) => block,
+                        _ => continue,
+                    };
+                    
+                    let origin = [x as f32, y as f32, z as f32];
+                    
+                    for b in registry.shape(block).boxes() {
+                        let pos = [translation.x, translation.y, translation.z];
+                        
+                        // The shortest move out of the box along each axis.
+                        let mut push = [0.; 3];
+                        for axis in 0..3 {
+                            let below = origin[axis] + b.min[axis] - COLLIDE_PADDING - pos[axis];
+                            let above = origin[axis] + b.max[axis] + COLLIDE_PADDING - pos[axis];
+                            
+                            push[axis] = if below >= 0. || above <= 0. {
+                                0.
+                            } else if -below < above {
+                                below
+                            } else {
+                                above
+                            };
+                        }
+                        
+                        // The player is clear of the box
+                        // if it is clear along any axis.
+                        if push.contains(&0.) {
+                            continue;
+                        }
+                        
+                        let axis = (0..3).min_by(|&i, &j| {
+                            push[i].abs().partial_cmp(&push[j].abs()).unwrap()
+                        }).unwrap();
+                        
+                        match axis {
+                            0 => translation.x += push[0],
+                            1 => translation.y += push[1],
+                            _ => translation.z += push[2],
+                        }
+                    }
+                }
             }
-            
-            //println!("{:?}, {:?}", self.get_visible_block(&below_t), *translation);
-
+        }
+    }
+    
+    // Get the block at this position in **world** block coords.
+    // If the sector is generated but not rendered, `None`
+    // is returned.
+    fn get_visible_block(&self, pos: (i32, i32, i32)) -> Option<&BlockId> {
+        let (sector_pos, local) = split_world_coords(pos);
+        let sector = self.sectors.get(&sector_pos)?;
+        
+        if !sector.has_models() &&
+           sector.blocks().needs_rendering(self.resources.block_registry()) {
+            return None;
         }
         
-        //
+        Some(sector.blocks().get(local))
+    }
+    
+    // Ask the workers to light a sector that was generated or
+    // loaded, unless it is loaded already. The job goes to the
+    // front of the queue, so that the sector is loaded soon.
+    fn queue_light(&self, sector_coords: (i32, i32, i32), blocks: BlockList, open_sky: bool) {
+        if self.sectors.contains_key(&sector_coords) {
+            return;
+        }
         
-        {
-            let left_t = Translation::new(translation.x.round() - 1., translation.y, translation.z);
-            let left = match self.get_visible_block(&left_t) {
-                Some(l) => !l.is_air(),
-                None => false,
-            };
+        let snapshot = |face: Face| {
+            let normal = face.normal();
+            let coords = (sector_coords.0 + normal.0,
+                          sector_coords.1 + normal.1,
+                          sector_coords.2 + normal.2);
             
-            let margin = left_t.x + 1. + COLLIDE_PADDING;
-            if left && translation.x < margin {
-                translation.x = margin;
-            }
+            self.sectors.get(&coords).map(|sector| sector.light_snapshot())
+        };
+        
+        let job = LightJob {
+            sector: sector_coords,
+            blocks,
+            open_sky,
+            registry: self.resources.block_registry().clone(),
+            neighbors: [snapshot(FACES[0]), snapshot(FACES[1]), snapshot(FACES[2]),
+                        snapshot(FACES[3]), snapshot(FACES[4]), snapshot(FACES[5])],
+        };
+        
+        self.jobs.lock().unwrap().push_front(Job::Light(job));
+    }
+    
+    // Insert a sector that was lit by the workers, unless it is
+    // loaded already, and spread light into and out of it. If its
+    // neighbors lost light in the meantime, it is lit again.
+    fn add_sector(&mut self, job: LightJob, light: LightList) {
+        if self.sectors.contains_key(&job.sector) {
+            return;
         }
         
-        //
+        if light::is_stale(&self.sectors, job.sector, &job.neighbors, job.open_sky) {
+            self.queue_light(job.sector, job.blocks, job.open_sky);
+            return;
+        }
         
-        {
-            let right_t = Translation::new(translation.x.round() + 1., translation.y, translation.z);
-            let right = match self.get_visible_block(&right_t) {
-                Some(r) => !r.is_air(),
-                None => false,
-            };
-            
-            let margin = right_t.x - 1. - COLLIDE_PADDING;
-            if right && translation.x > margin {
-                translation.x = margin;
+        let mut sector = Sector::new(job.blocks, light);
+        if job.open_sky {
+            sector.mark_open_sky();
+        }
+        
+        self.sectors.insert(job.sector, sector);
+        
+        let changed = light::light_sector(&mut self.sectors, self.resources.block_registry(),
+                                          job.sector);
+        self.invalidate_models(changed);
+        
+        // The sectors that share an edge or a corner were
+        // meshed without the blocks of this one at their
+        // corners, which shade and light those corners.
+        let (x, y, z) = job.sector;
+        self.invalidate_models(DIAGONALS.iter().map(|d| (x + d.0, y + d.1, z + d.2)).collect());
+    }
+    
+    // Mark the sectors whose models are, or are about to
+    // be, out of date as dirty, so that they are rebuilt.
+    fn invalidate_models(&mut self, sectors: HashSet<(i32, i32, i32)>) {
+        for coords in sectors {
+            if let Some(sector) = self.sectors.get_mut(&coords) {
+                if sector.has_models() || sector.is_meshing() {
+                    sector.mark_dirty();
+                }
             }
         }
     }
     
-    // Get the block at this position in **world** coords.
-    // If the sector is generated but not rendered, `None`
-    // is returned.
-    fn get_visible_block(&self, pos: &Translation) -> Option<&Block> {
-        let sector_pos = sector_at(pos);
+    // Snapshot a sector and its 6 neighbors for meshing, if
+    // they are all loaded, along with the neighbors sharing
+    // an edge or a corner that are loaded.
+    fn mesh_job(&self, sector_coords: (i32, i32, i32)) -> Option<MeshJob> {
+        let back   = (sector_coords.0,     sector_coords.1,     sector_coords.2 - 1);
+        let front  = (sector_coords.0,     sector_coords.1,     sector_coords.2 + 1);
+        let top    = (sector_coords.0,     sector_coords.1 + 1, sector_coords.2    );
+        let bottom = (sector_coords.0,     sector_coords.1 - 1, sector_coords.2    );
+        let left   = (sector_coords.0 - 1, sector_coords.1,     sector_coords.2    );
+        let right  = (sector_coords.0 + 1, sector_coords.1,     sector_coords.2    );
         
-        let pos = (pos.x.round() as i32, pos.y.round() as i32, pos.z.round() as i32);
+        let snapshot = |coords| self.sectors.get(&coords).map(|sector| {
+            (sector.snapshot(), sector.light_snapshot())
+        });
+        let sector = self.sectors.get(&sector_coords)?;
         
-        if let Some(sector) = self.sectors.get(&sector_pos) {
-            if sector.model().is_none() && sector.blocks().needs_rendering() {
-                return None;
-            }
-            
-            let local = SectorSpaceCoords::new((pos.0 - sector_pos.0 * SECTOR_SIZE as i32) as u8,
-                                               (pos.1 - sector_pos.1 * SECTOR_SIZE as i32) as u8,
-                                               (pos.2 - sector_pos.2 * SECTOR_SIZE as i32) as u8);
-            
-            Some(sector.blocks().get(local))
-        } else {
-            None
+        let (x, y, z) = sector_coords;
+        let diagonals = DIAGONALS.iter().filter_map(|&d| {
+            snapshot((x + d.0, y + d.1, z + d.2)).map(|neighbor| (d, neighbor))
+        }).collect();
+        
+        Some(MeshJob {
+            sector: sector_coords,
+            revision: sector.revision(),
+            mode: self.mesh_mode,
+            atlas: self.resources.terrain_atlas().clone(),
+            registry: self.resources.block_registry().clone(),
+            blocks: sector.snapshot(),
+            light: sector.light_snapshot(),
+            adjacent: [snapshot(back)?,
+                       snapshot(front)?,
+                       snapshot(top)?,
+                       snapshot(bottom)?,
+                       snapshot(left)?,
+                       snapshot(right)?],
+            diagonals,
+        })
+    }
+    
+    // Ask the workers to mesh a sector. The job goes to the
+    // front of the queue, so that edits show up quickly. If the
+    // neighbors are not loaded, nothing happens.
+    fn queue_mesh(&mut self, sector_coords: (i32, i32, i32)) {
+        let job = match self.mesh_job(sector_coords) {
+            Some(job) => job,
+            None => return,
+        };
+        
+        self.jobs.lock().unwrap().push_front(Job::Mesh(job));
+        self.sectors.get_mut(&sector_coords).unwrap().mark_meshing();
+    }
+    
+    // Start recreating the model of a sector. A sector that
+    // no longer needs rendering loses its model at once. If
+    // the neighbors are not loaded, the sector stays dirty and
+    // is retried on the next update.
+    fn rebuild_model(&mut self, sector_coords: (i32, i32, i32)) {
+        if !self.sectors[&sector_coords].blocks().needs_rendering(self.resources.block_registry()) {
+            self.sectors.get_mut(&sector_coords).unwrap().set_models(None);
+            return;
         }
+        
+        self.queue_mesh(sector_coords);
     }
     
-    fn load_shaders() ->
-            Result<(Program<Vertex, (), Uniforms>, Vec<UniformWarning>), ProgramError> {
+    fn load_shaders() -> Result<(TerrainProgram, Vec<UniformWarning>), ProgramError> {
         
         let (vs, fs) = shader::load_shader_text("vs", "fs");
         
@@ -353,34 +576,68 @@ impl<'a> Drawable for Terrain<'a> {
                 // that the correct one is used.
                 pipeline(render_target, CLEAR_COLOR, |shade_gate| {
                     //let mut skipped = 0;
-                    //let mut air = 0;
                     
-                    for i in &self.sectors {
-                        if let Some(model) = i.1.model() {
-                            if !sector_visible(&frustum, *i.0) {
+                    // Opaque blocks are drawn first, then cutout blocks
+                    // with their see-through texels discarded, and then
+                    // translucent blocks blended over both of them.
+                    for &pass in &RENDER_PASSES {
+                        let mut models: Vec<_> = self.sectors.iter().filter_map(|(&coords, sector)| {
+                            if !sector_visible(&frustum, coords) {
                                 //skipped += 1;
-                                continue;
+                                return None;
                             }
                             
+                            sector.model(pass).map(|model| (coords, model))
+                        }).collect();
+                        
+                        let (alpha_cutoff, blending) = match pass {
+                            RenderPass::Opaque => (0., None),
+                            RenderPass::Cutout => (0.5, None),
+                            RenderPass::Translucent => {
+                                // Blending needs the farthest sectors to be
+                                // drawn first, so that nearer ones cover them.
+                                let eye = camera.translation();
+                                let distance = |coords: (i32, i32, i32)| {
+                                    let center = |c: i32| (c * SECTOR_SIZE as i32) as f32 +
+                                                          SECTOR_SIZE as f32 / 2.;
+                                    let x = center(coords.0) - eye.x;
+                                    let y = center(coords.1) - eye.y;
+                                    let z = center(coords.2) - eye.z;
+                                    
+                                    x * x + y * y + z * z
+                                };
+                                
+                                models.sort_by(|a, b| {
+                                    distance(b.0).partial_cmp(&distance(a.0)).unwrap()
+                                });
+                                
+                                (0., Some((Equation::Additive,
+                                           Factor::SrcAlpha,
+                                           Factor::SrcAlphaComplement)))
+                            },
+                        };
+                        
+                        for (_, model) in models {
                             gpu.bind_texture(&model.tex.0);
                             shade_gate.shade(&self.shader, |render_gate, uniforms| {
                                 uniforms.model_matrix.update(model.to_matrix());
                                 uniforms.view_matrix.update(camera.to_matrix());
                                 uniforms.projection_matrix.update(*camera.projection_matrix());
+                                uniforms.tile_size.update(self.resources.terrain_atlas().tile_size());
+                                uniforms.alpha_cutoff.update(alpha_cutoff);
                                 //uniforms.terrain_tex.update(bound);
                                 
-                                let render_state = RenderState::default();
+                                let render_state = RenderState::default()
+                                                   .set_blending(blending);
                                                    //.set_face_culling(None);
                                 render_gate.render(render_state, |tess_gate| {
                                     tess_gate.render((&model.tess).into());
                                 });
                             });
-                        }/* else {
-                            air += 1;
-                        }*/
+                        }
                     }
                     
-                    //println!("skipped: {} / {})", skipped, self.sectors.len() - air);
+                    //println!("skipped: {}", skipped);
                 });
             });
         });
@@ -398,23 +655,33 @@ struct Uniforms {
     // 3D Projection.
     projection_matrix: Uniform<M44>,
     
+    // Size of one tile in the texture atlas.
+    tile_size: Uniform<[f32; 2]>,
+    
+    // Texels less opaque than this are discarded.
+    alpha_cutoff: Uniform<f32>,
+    
     // Terrain Texture Atlas.
     //pub terrain_tex: Uniform<BoundTexture<'a, Texture<Flat, Dim2, RGB8UI>>>,
 }
 
-impl<'a> UniformInterface for Uniforms {
+impl UniformInterface for Uniforms {
     fn uniform_interface(builder: UniformBuilder)
             -> Result<(Uniforms, Vec<UniformWarning>), ProgramError> {
         
         let model_matrix = builder.ask("model_matrix").unwrap();
         let view_matrix = builder.ask("view_matrix").unwrap();
         let projection_matrix = builder.ask("projection_matrix").unwrap();
+        let tile_size = builder.ask("tile_size").unwrap();
+        let alpha_cutoff = builder.ask("alpha_cutoff").unwrap();
         //let terrain_tex = builder.ask("terrain_tex").unwrap();
         
         Ok((Uniforms {
             model_matrix,
             view_matrix,
             projection_matrix,
+            tile_size,
+            alpha_cutoff,
             //terrain_tex,
         }, Vec::new()))
     }
@@ -443,34 +710,198 @@ enum Nearby {
         sector: (i32, i32, i32),
         should_render: bool,
     },
-    Generated((i32, i32, i32), BlockList),
+    // A sector that was loaded from disk, or generated.
+    Generated {
+        sector: (i32, i32, i32),
+        blocks: BlockList,
+        open_sky: bool,
+    },
+    Lit {
+        job: LightJob,
+        light: LightList,
+    },
+    Meshed {
+        sector: (i32, i32, i32),
+        revision: usize,
+        meshes: mesh_gen::PassMeshes,
+    },
+}
+
+// Work for the worker threads.
+enum Job {
+    // Load or generate a sector. Sectors above the surface
+    // of their column are open to the sky.
+    Generate {
+        sector: (i32, i32, i32),
+        open_sky: bool,
+    },
+    Light(LightJob),
+    Mesh(MeshJob),
+    
+    // Save an unloaded sector from `PendingSaves`.
+    Save((i32, i32, i32)),
+}
+
+type JobQueue = Arc<Mutex<VecDeque<Job>>>;
+
+// The blocks and light of a sector, shared with a worker thread.
+type SectorSnapshot = (Arc<BlockList>, Arc<LightList>);
+
+// A sector to be lit by a worker thread before it is loaded. The
+// light of the neighbors is a snapshot, and is checked again when
+// the sector is loaded.
+struct LightJob {
+    sector: (i32, i32, i32),
+    blocks: BlockList,
+    open_sky: bool,
+    registry: Arc<BlockRegistry>,
+    neighbors: NeighborLight,
+}
+
+impl LightJob {
+    fn run(self) -> Nearby {
+        let light = light::light_blocks(&self.blocks, &self.neighbors, self.open_sky,
+                                        &self.registry);
+        
+        Nearby::Lit {
+            job: self,
+            light,
+        }
+    }
+}
+
+// A sector to be meshed by a worker thread. The blocks are
+// snapshots, so the sectors may change while the mesh is built.
+struct MeshJob {
+    sector: (i32, i32, i32),
+    revision: usize,
+    mode: MeshMode,
+    atlas: Arc<Atlas>,
+    registry: Arc<BlockRegistry>,
+    blocks: Arc<BlockList>,
+    light: Arc<LightList>,
+    
+    // The blocks and light of the back, front, top,
+    // bottom, left, and right neighbors.
+    adjacent: [SectorSnapshot; 6],
+    
+    // The blocks and light of the loaded neighbors that
+    // share an edge or a corner, by their offset.
+    diagonals: Vec<((i32, i32, i32), SectorSnapshot)>,
+}
+
+impl MeshJob {
+    fn run(self) -> Nearby {
+        let meshes = {
+            let a = &self.adjacent;
+            let mut adjacent = AdjacentSectors::new(&a[0].0, &a[1].0, &a[2].0,
+                                                    &a[3].0, &a[4].0, &a[5].0);
+            let mut adjacent_light = AdjacentLight::new(&a[0].1, &a[1].1, &a[2].1,
+                                                        &a[3].1, &a[4].1, &a[5].1);
+            for &(offset, (ref blocks, ref light)) in &self.diagonals {
+                adjacent.set_diagonal(offset, blocks);
+                adjacent_light.set_diagonal(offset, light);
+            }
+            
+            mesh_gen::generate_vertices(self.mode, &self.blocks, &adjacent,
+                                        &self.light, &adjacent_light,
+                                        &self.atlas, &self.registry)
+        };
+        
+        Nearby::Meshed {
+            sector: self.sector,
+            revision: self.revision,
+            meshes,
+        }
+    }
 }
 
 const GENERATE_ORDER: [i32; 7] = [0, -1, 1, -2, 2, 3, -3];
 const RENDER_DIST_AXIS: i32 = 2;
+const RENDER_DIST_Y: i32 = 3;
 const NUM_WORKERS: usize = 8;
 
+type SharedStore = Arc<Mutex<RegionStore>>;
+
+// Snapshots of the sectors that were unloaded but not yet saved.
+// They are loaded from here rather than from the older copy on disk.
+type PendingSaves = Arc<Mutex<HashMap<(i32, i32, i32), Arc<BlockList>>>>;
+type SharedGenerator = Arc<dyn WorldGenerator>;
+
 struct TerrainGenThread {
     shared_info: SharedInfo,
+    store: SharedStore,
+    pending_saves: PendingSaves,
+    jobs: JobQueue,
     nearby_tx: Sender<Nearby>,
     needed_rx: Receiver<(i32, i32, i32)>,
+    gen: SharedGenerator,
+    
+    // The surface rows of the columns seen so far, which
+    // are slow to find and never change.
+    surface_rows: HashMap<(i32, i32), (i32, i32)>,
 }
 
 impl TerrainGenThread {
     fn new(shared_info: SharedInfo,
+           store: SharedStore,
+           pending_saves: PendingSaves,
+           jobs: JobQueue,
            nearby_tx: Sender<Nearby>,
-           needed_rx: Receiver<(i32, i32, i32)>) -> TerrainGenThread {
+           needed_rx: Receiver<(i32, i32, i32)>,
+           gen: SharedGenerator) -> TerrainGenThread {
         TerrainGenThread {
             shared_info,
+            store,
+            pending_saves,
+            jobs,
             nearby_tx,
             needed_rx,
+            gen,
+            surface_rows: HashMap::new(),
+        }
+    }
+    
+    fn surface_rows(&mut self, column: (i32, i32)) -> (i32, i32) {
+        let gen = &self.gen;
+        
+        *self.surface_rows.entry(column).or_insert_with(|| {
+            gen.surface_rows(column.0, column.1)
+        })
+    }
+    
+    // The rows of sectors to generate in a column: those that hold
+    // the surface, and those around the player. Rows are listed from
+    // the top down, so that sky light reaches each sector from above
+    // instead of being spread and then taken back.
+    //
+    // A sector is only meshed once its 6 neighbors are loaded, so the
+    // rows of the surface in the 4 columns next to this one are
+    // generated as well, along with one more row above and below.
+    fn rows(&mut self, column: (i32, i32), player_row: i32) -> Vec<i32> {
+        let (mut low, mut high) = self.surface_rows(column);
+        
+        for &(dx, dz) in &[(-1, 0), (1, 0), (0, -1), (0, 1)] {
+            let (l, h) = self.surface_rows((column.0 + dx, column.1 + dz));
+            
+            low = low.min(l);
+            high = high.max(h);
         }
+        
+        let mut rows: Vec<_> = (low - 1..high + 2).chain(player_row - 1..player_row + 2)
+                                                  .collect();
+        rows.sort_by(|a, b| b.cmp(a));
+        rows.dedup();
+        
+        rows
     }
     
-    fn spawn(self) {
-        let gen = WorldGen::new();
-        let queue = Arc::new(Mutex::new(VecDeque::new()));
+    fn spawn(mut self) {
+        let gen = self.gen.clone();
+        let queue = self.jobs.clone();
         let nearby_tx = self.nearby_tx.clone();
+        let store = self.store.clone();
+        let pending_saves = self.pending_saves.clone();
         
         let queue1 = queue.clone();
         thread::spawn(move || {
@@ -484,14 +915,15 @@ impl TerrainGenThread {
                 //println!("{:?}", sector);
                 
                 for dx in &GENERATE_ORDER {
-                    for dy in -3..1 {
-                        for dz in &GENERATE_ORDER {
+                    for dz in &GENERATE_ORDER {
+                        for y in self.rows((sector.0 + dx, sector.2 + dz), sector.1) {
+                            let dy = y - sector.1;
                             let sector = (sector.0 + dx,
-                                          sector.1 + dy,
+                                          y,
                                           sector.2 + dz);
                             
                             let should_render = dx.abs() <= RENDER_DIST_AXIS &&
-                                                dy.abs() <= 1 &&
+                                                dy.abs() <= RENDER_DIST_Y &&
                                                 dz.abs() <= RENDER_DIST_AXIS;
                             
                             if self.nearby_tx.send(Nearby::Query { sector, should_render }).is_err() {
@@ -521,7 +953,10 @@ impl TerrainGenThread {
                     //if self.nearby_tx.send(Nearby::Generated(needed, list)).is_err() {
                     //    return;
                     //}
-                    queue1.lock().unwrap().push_back(needed);
+                    let (_, high) = self.surface_rows((needed.0, needed.2));
+                    let job = Job::Generate { sector: needed, open_sky: needed.1 > high };
+                    
+                    queue1.lock().unwrap().push_back(job);
                     //println!("push: {:?}", needed);
                 }
                 
@@ -534,6 +969,8 @@ impl TerrainGenThread {
             let gen = gen.clone();
             let queue = queue.clone();
             let nearby_tx = nearby_tx.clone();
+            let store = store.clone();
+            let pending_saves = pending_saves.clone();
             
             thread::spawn(move || {
                 loop {
@@ -541,21 +978,104 @@ impl TerrainGenThread {
                     //println!("size: {} ({})", q.len(), i);
                     //mem::drop(q);
                     
-                    if let Some(coords) = item {
-                        let block_list = gen.generate(coords);
-                        
-                        if nearby_tx.send(Nearby::Generated(coords, block_list)).is_err() {
-                            return;
-                        }
-                    }
+                    let nearby = match item {
+                        Some(Job::Generate { sector, open_sky }) => {
+                            // Sectors that were saved before are
+                            // loaded instead of generated.
+                            let pending = pending_saves.lock().unwrap().get(&sector).cloned();
+                            let loaded = match pending {
+                                Some(blocks) => Ok(Some((*blocks).clone())),
+                                None => store.lock().unwrap().load(sector),
+                            };
+                            
+                            let blocks = match loaded {
+                                Ok(Some(blocks)) => blocks,
+                                Ok(None) => gen.generate(sector),
+                                Err(e) => {
+                                    eprintln!("Could not load sector {:?}: {}", sector, e);
+                                    
+                                    gen.generate(sector)
+                                },
+                            };
+                            
+                            Nearby::Generated { sector, blocks, open_sky }
+                        },
+                        Some(Job::Light(job)) => job.run(),
+                        Some(Job::Mesh(job)) => job.run(),
+                        Some(Job::Save(sector)) => {
+                            save_unloaded(&store, &pending_saves, sector);
+                            continue;
+                        },
+                        None => {
+                            thread::sleep(Duration::from_millis(5));
+                            continue;
+                        },
+                    };
                     
-                    thread::sleep(Duration::from_millis(5));
+                    if nearby_tx.send(nearby).is_err() {
+                        return;
+                    }
                 }
             });
         }
     }
 }
 
+// Write a sector to disk if it has changed.
+fn save_sector(store: &SharedStore, coords: (i32, i32, i32), sector: &mut Sector) {
+    if sector.is_saved() {
+        return;
+    }
+    
+    match store.lock().unwrap().save(coords, sector.blocks()) {
+        Ok(()) => sector.mark_saved(),
+        Err(e) => eprintln!("Could not save sector {:?}: {}", coords, e),
+    }
+}
+
+// Write the snapshot of an unloaded sector to disk. The store
+// stays locked throughout, so that when a sector is unloaded
+// again while it is saved, the newer snapshot is written last.
+fn save_unloaded(store: &SharedStore, pending_saves: &PendingSaves,
+                 coords: (i32, i32, i32)) {
+    let store = store.lock().unwrap();
+    let blocks = match pending_saves.lock().unwrap().get(&coords) {
+        Some(blocks) => blocks.clone(),
+        None => return,
+    };
+    
+    match store.save(coords, &blocks) {
+        Ok(()) => {
+            let mut pending_saves = pending_saves.lock().unwrap();
+            if pending_saves.get(&coords).is_some_and(|b| Arc::ptr_eq(b, &blocks)) {
+                pending_saves.remove(&coords);
+            }
+        },
+        Err(e) => eprintln!("Could not save sector {:?}: {}", coords, e),
+    }
+}
+
+// Split **world** block coords into the coords of the
+// containing sector and the coords within that sector.
+fn split_world_coords(pos: (i32, i32, i32)) -> ((i32, i32, i32), SectorSpaceCoords) {
+    fn split(c: i32) -> (i32, u8) {
+        let size = SECTOR_SIZE as i32;
+        let sector = if c >= 0 {
+            c / size
+        } else {
+            (c + 1) / size - 1
+        };
+        
+        (sector, (c - sector * size) as u8)
+    }
+    
+    let (sx, lx) = split(pos.0);
+    let (sy, ly) = split(pos.1);
+    let (sz, lz) = split(pos.2);
+    
+    ((sx, sy, sz), SectorSpaceCoords::new(lx, ly, lz))
+}
+
 // The nearest sector at a translation.
 fn sector_at(pos: &Translation) -> (i32, i32, i32) {
     ((pos.x.round() / SECTOR_SIZE as f32).floor() as i32,
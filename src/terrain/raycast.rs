@@ -0,0 +1,249 @@
+//! Voxel raycasting, used to find the block the player looks at.
+
+use super::mesh_gen::Face;
+use super::voxel::BlockId;
+
+/// A block struck by a ray.
+#[derive(Clone, Copy, Debug)]
+pub struct RayHit {
+    /// The position of the block in **world** block coords.
+    pub pos: (i32, i32, i32),
+    
+    /// The block that was hit.
+    pub block: BlockId,
+    
+    /// The face of the block through which the ray entered.
+    pub face: Face,
+    
+    /// The distance from the start of the ray to the face.
+    pub distance: f32,
+}
+
+/// The outcome of a raycast.
+#[derive(Clone, Copy, Debug)]
+pub enum Raycast {
+    /// The ray hit a block that is not air.
+    Hit(RayHit),
+    
+    /// The ray passed only through air for its whole length.
+    Miss,
+    
+    /// Before hitting anything, the ray reached a sector that
+    /// is not loaded. Holds the distance at which that happened.
+    Unloaded(f32),
+}
+
+/// Cast a ray through the voxel grid, using the DDA algorithm
+/// of Amanatides and Woo. The block at world block coords
+/// `(x, y, z)` occupies the unit cube starting at `(x, y, z)`.
+///
+/// `get_block` is asked for blocks in world block coords, and
+/// returns `None` if the block is not loaded.
+/// `direction` need not be normalized.
+///
+/// If the ray starts inside a solid block, that block is hit at
+/// distance 0, on the face that points back along the ray.
+pub fn cast<F>(origin: (f32, f32, f32), direction: (f32, f32, f32),
+               max_distance: f32, get_block: F) -> Raycast
+        where F: Fn((i32, i32, i32)) -> Option<BlockId> {
+    let length = (direction.0 * direction.0 +
+                  direction.1 * direction.1 +
+                  direction.2 * direction.2).sqrt();
+    if length == 0. {
+        return Raycast::Miss;
+    }
+    
+    let origin = [origin.0, origin.1, origin.2];
+    let dir = [direction.0 / length, direction.1 / length, direction.2 / length];
+    
+    let mut pos = [origin[0].floor() as i32, origin[1].floor() as i32, origin[2].floor() as i32];
+    let mut step = [0; 3];
+    let mut t_max = [0.; 3];
+    let mut t_delta = [0.; 3];
+    
+    for axis in 0..3 {
+        if dir[axis] > 0. {
+            step[axis] = 1;
+            t_delta[axis] = 1. / dir[axis];
+            t_max[axis] = (pos[axis] as f32 + 1. - origin[axis]) * t_delta[axis];
+        } else if dir[axis] < 0. {
+            step[axis] = -1;
+            t_delta[axis] = -1. / dir[axis];
+            t_max[axis] = (origin[axis] - pos[axis] as f32) * t_delta[axis];
+        } else {
+            t_delta[axis] = f32::INFINITY;
+            t_max[axis] = f32::INFINITY;
+        }
+    }
+    
+    // The face that points back along the ray, on the
+    // axis along which the ray travels the most.
+    let mut axis = (0..3).fold(0, |best, a| if dir[a].abs() > dir[best].abs() { a } else { best });
+    let mut distance = 0.;
+    
+    loop {
+        match get_block((pos[0], pos[1], pos[2])) {
+            None => return Raycast::Unloaded(distance),
+            Some(block) => if !block.is_air() {
+                return Raycast::Hit(RayHit {
+                    pos: (pos[0], pos[1], pos[2]),
+                    block,
+                    face: entry_face(axis, step[axis]),
+                    distance,
+                });
+            },
+        }
+        
+        axis = if t_max[0] < t_max[1] && t_max[0] < t_max[2] {
+            0
+        } else if t_max[1] < t_max[2] {
+            1
+        } else {
+            2
+        };
+        
+        distance = t_max[axis];
+        if distance > max_distance {
+            return Raycast::Miss;
+        }
+        
+        pos[axis] += step[axis];
+        t_max[axis] += t_delta[axis];
+    }
+}
+
+// The face through which a ray enters a block when
+// it steps along `axis` in the direction `step`.
+fn entry_face(axis: usize, step: i32) -> Face {
+    match (axis, step > 0) {
+        (0, true) => Face::Left,
+        (0, false) => Face::Right,
+        (1, true) => Face::Bottom,
+        (1, false) => Face::Top,
+        (_, true) => Face::Back,
+        (_, false) => Face::Front,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::*;
+    use super::super::split_world_coords;
+    use super::super::mesh_gen::FACES;
+    use super::super::voxel::BlockList;
+    
+    type World = HashMap<(i32, i32, i32), BlockList>;
+    
+    fn stone() -> BlockId {
+        BlockId::new(1)
+    }
+    
+    // Air in each of `sectors`, with stone at each of `blocks`,
+    // in **world** block coords.
+    fn air_with_stone(sectors: &[(i32, i32, i32)], blocks: &[(i32, i32, i32)]) -> World {
+        let mut world: World = sectors.iter().map(|&s| (s, BlockList::new_air())).collect();
+        
+        for &pos in blocks {
+            let (sector, local) = split_world_coords(pos);
+            world.get_mut(&sector).unwrap().set(local, stone());
+        }
+        
+        world
+    }
+    
+    fn cast_in(world: &World, origin: (f32, f32, f32), direction: (f32, f32, f32),
+               max_distance: f32) -> Raycast {
+        cast(origin, direction, max_distance, |pos| {
+            let (sector, local) = split_world_coords(pos);
+            
+            world.get(&sector).map(|blocks| *blocks.get(local))
+        })
+    }
+    
+    fn expect_hit(raycast: Raycast) -> RayHit {
+        match raycast {
+            Raycast::Hit(hit) => hit,
+            other => panic!("expected a hit, got {:?}", other),
+        }
+    }
+    
+    fn assert_near(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-4, "{} is not {}", a, b);
+    }
+    
+    #[test]
+    fn hit_inside_sector() {
+        let world = air_with_stone(&[(0, 0, 0)], &[(5, 3, 4)]);
+        
+        let straight = expect_hit(cast_in(&world, (1.5, 3.5, 4.5), (1., 0., 0.), 100.));
+        assert_eq!(straight.pos, (5, 3, 4));
+        assert_eq!(straight.block, stone());
+        assert_eq!(straight.face, Face::Left);
+        assert_near(straight.distance, 3.5);
+        
+        // Enters the block through its left face at y = 3.25.
+        let slanted = expect_hit(cast_in(&world, (1.5, 1.5, 4.5), (4., 2., 0.), 100.));
+        assert_eq!(slanted.pos, (5, 3, 4));
+        assert_eq!(slanted.face, Face::Left);
+        assert_near(slanted.distance, 3.5 * 20f32.sqrt() / 4.);
+    }
+    
+    #[test]
+    fn hit_across_sectors() {
+        let sectors = [(0, 0, 0), (-1, 0, 0), (0, -1, 0), (-1, -1, 0)];
+        let world = air_with_stone(&sectors, &[(-3, 2, 5), (-2, -3, 5)]);
+        
+        let straight = expect_hit(cast_in(&world, (4.5, 2.5, 5.5), (-1., 0., 0.), 100.));
+        assert_eq!(straight.pos, (-3, 2, 5));
+        assert_eq!(straight.face, Face::Right);
+        assert_near(straight.distance, 6.5);
+        
+        // Crosses x = 0 and then y = 0 before entering the
+        // top of the block at x = -1.25.
+        let slanted = expect_hit(cast_in(&world, (0.5, 1.5, 5.5), (-2., -4., 0.), 100.));
+        assert_eq!(slanted.pos, (-2, -3, 5));
+        assert_eq!(slanted.face, Face::Top);
+        assert_near(slanted.distance, 3.5 * 1.25f32.sqrt());
+    }
+    
+    #[test]
+    fn miss_and_unloaded() {
+        let world = air_with_stone(&[(0, 0, 0)], &[]);
+        
+        match cast_in(&world, (16.5, 16.5, 16.5), (1., 0., 0.), 10.) {
+            Raycast::Miss => {},
+            other => panic!("expected a miss, got {:?}", other),
+        }
+        
+        match cast_in(&world, (16.5, 16.5, 16.5), (1., 0., 0.), 100.) {
+            Raycast::Unloaded(distance) => assert_near(distance, 15.5),
+            other => panic!("expected an unloaded sector, got {:?}", other),
+        }
+        
+        // A block beyond the end of the ray is not hit.
+        let world = air_with_stone(&[(0, 0, 0)], &[(20, 16, 16)]);
+        match cast_in(&world, (16.5, 16.5, 16.5), (1., 0., 0.), 3.) {
+            Raycast::Miss => {},
+            other => panic!("expected a miss, got {:?}", other),
+        }
+    }
+    
+    #[test]
+    fn hit_along_each_axis() {
+        let world = air_with_stone(&[(0, 0, 0)], &[(16, 16, 16)]);
+        
+        for &face in &FACES {
+            let normal = face.normal();
+            let origin = (16.5 + 5. * normal.0 as f32,
+                          16.5 + 5. * normal.1 as f32,
+                          16.5 + 5. * normal.2 as f32);
+            let direction = (-normal.0 as f32, -normal.1 as f32, -normal.2 as f32);
+            
+            let hit = expect_hit(cast_in(&world, origin, direction, 100.));
+            assert_eq!(hit.pos, (16, 16, 16));
+            assert_eq!(hit.face, face);
+            assert_near(hit.distance, 4.5);
+        }
+    }
+}
@@ -0,0 +1,397 @@
+//! On-disk storage of sectors, grouped into region files.
+//!
+//! Each region file holds a cube of `REGION_SIZE`^3 sectors.
+//! The file begins with an offset table that has one entry per
+//! sector, each entry being a little endian `u32` byte offset
+//! followed by a `u32` byte length. A length of zero means that
+//! the sector has not been saved. The sector data itself is a
+//! run length encoding of the `BlockList`, stored as pairs of a
+//! little endian `u16` run length and a `u8` block id, in the
+//! order in which the `BlockList` iterates.
+//!
+//! The data of the sectors may be stored in any order. A sector
+//! that is saved again is written to the first gap between the
+//! data of the other sectors that is large enough, or after all of
+//! it, so that the space of old copies is used again.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use super::SECTOR_SIZE;
+use super::voxel::{BlockId, BlockList, SectorSpaceCoords};
+
+/// The length of one side of a cubic region, in sectors.
+pub const REGION_SIZE: i32 = 16;
+
+// The number of sectors in a region.
+const REGION_LEN: usize = (REGION_SIZE * REGION_SIZE * REGION_SIZE) as usize;
+
+// The size of one entry of the offset table.
+const ENTRY_SIZE: usize = 8;
+
+// The size of the offset table at the start of the file.
+const HEADER_SIZE: usize = REGION_LEN * ENTRY_SIZE;
+
+// The size of one run in the sector data.
+const RUN_SIZE: usize = 3;
+
+/// Loads and saves `BlockList`s in region files
+/// stored in one directory.
+pub struct RegionStore {
+    dir: PathBuf,
+}
+
+impl RegionStore {
+    /// Create a store that keeps its region files in `dir`.
+    /// The directory is created when the first sector is saved.
+    pub fn new<P: AsRef<Path>>(dir: P) -> RegionStore {
+        RegionStore {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+    
+    /// Load a sector from disk.
+    /// Returns `Ok(None)` if the sector was never saved.
+    pub fn load(&self, sector: (i32, i32, i32)) -> io::Result<Option<BlockList>> {
+        let (region, index) = locate(sector);
+        
+        let mut file = match File::open(self.region_path(region)) {
+            Ok(f) => f,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        
+        let (offset, len) = read_entry(&mut file, index)?;
+        if len == 0 {
+            return Ok(None);
+        }
+        
+        let mut data = vec![0; len as usize];
+        file.seek(SeekFrom::Start(offset as u64))?;
+        file.read_exact(&mut data)?;
+        
+        decode(&data).map(Some)
+    }
+    
+    /// Save a sector to disk, replacing any previous copy.
+    pub fn save(&self, sector: (i32, i32, i32), blocks: &BlockList) -> io::Result<()> {
+        let (region, index) = locate(sector);
+        
+        fs::create_dir_all(&self.dir)?;
+        let mut file = OpenOptions::new().read(true)
+                                         .write(true)
+                                         .create(true)
+                                         .truncate(false)
+                                         .open(self.region_path(region))?;
+        
+        let file_len = file.metadata()?.len();
+        if file_len < HEADER_SIZE as u64 {
+            file.set_len(HEADER_SIZE as u64)?;
+        }
+        
+        let data = encode(blocks);
+        let table = read_table(&mut file)?;
+        let offset = find_space(&table, data.len() as u64);
+        
+        let end = offset + data.len() as u64;
+        if end > u32::MAX as u64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "region file is full"));
+        }
+        
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(&data)?;
+        write_entry(&mut file, index, offset as u32, data.len() as u32)?;
+        
+        // Now that the entry points at the new copy, the old copy is
+        // free. Give back the space after the last sector.
+        let last = table.iter().enumerate()
+                        .filter(|&(i, _)| i != index)
+                        .map(|(_, &(offset, len))| offset as u64 + len as u64)
+                        .fold(end, u64::max);
+        if file_len > last {
+            file.set_len(last)?;
+        }
+        
+        Ok(())
+    }
+    
+    // The path of the file for a region.
+    fn region_path(&self, region: (i32, i32, i32)) -> PathBuf {
+        self.dir.join(format!("r.{}.{}.{}.region", region.0, region.1, region.2))
+    }
+}
+
+// Find the region containing a sector, and the index
+// of the sector in that region's offset table.
+fn locate(sector: (i32, i32, i32)) -> ((i32, i32, i32), usize) {
+    let (rx, lx) = split(sector.0);
+    let (ry, ly) = split(sector.1);
+    let (rz, lz) = split(sector.2);
+    
+    ((rx, ry, rz),
+     lx + ly * REGION_SIZE as usize + lz * (REGION_SIZE * REGION_SIZE) as usize)
+}
+
+// Split one sector coordinate into the region
+// coordinate and the offset within the region.
+fn split(c: i32) -> (i32, usize) {
+    let region = if c >= 0 {
+        c / REGION_SIZE
+    } else {
+        (c + 1) / REGION_SIZE - 1
+    };
+    
+    (region, (c - region * REGION_SIZE) as usize)
+}
+
+// Find where to write `len` bytes of data: the first gap between
+// the data of the sectors that is large enough, or the end of their
+// data. The old copy of the sector being saved stays reserved, so it
+// is still intact if writing the new copy fails.
+fn find_space(table: &[(u32, u32)], len: u64) -> u64 {
+    let mut used: Vec<_> = table.iter()
+                                .filter(|&&(_, len)| len != 0)
+                                .map(|&(offset, len)| (offset as u64, len as u64))
+                                .collect();
+    used.sort();
+    
+    let mut start = HEADER_SIZE as u64;
+    for (offset, used_len) in used {
+        if offset >= start + len {
+            break;
+        }
+        
+        start = start.max(offset + used_len);
+    }
+    
+    start
+}
+
+// Read the whole offset table.
+fn read_table(file: &mut File) -> io::Result<Vec<(u32, u32)>> {
+    let mut table = vec![0; HEADER_SIZE];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut table)?;
+    
+    Ok(table.chunks(ENTRY_SIZE).map(|entry| {
+        (read_u32(&entry[0..4]), read_u32(&entry[4..8]))
+    }).collect())
+}
+
+fn read_entry(file: &mut File, index: usize) -> io::Result<(u32, u32)> {
+    let mut entry = [0; ENTRY_SIZE];
+    file.seek(SeekFrom::Start((index * ENTRY_SIZE) as u64))?;
+    file.read_exact(&mut entry)?;
+    
+    Ok((read_u32(&entry[0..4]), read_u32(&entry[4..8])))
+}
+
+fn write_entry(file: &mut File, index: usize, offset: u32, len: u32) -> io::Result<()> {
+    let mut entry = [0; ENTRY_SIZE];
+    entry[0..4].copy_from_slice(&offset.to_le_bytes());
+    entry[4..8].copy_from_slice(&len.to_le_bytes());
+    
+    file.seek(SeekFrom::Start((index * ENTRY_SIZE) as u64))?;
+    file.write_all(&entry)
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+// Run length encode a `BlockList`.
+fn encode(blocks: &BlockList) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut run: Option<(BlockId, u16)> = None;
+    
+    for (_, block) in blocks {
+        run = match run {
+            Some((b, n)) if b == *block && n < u16::MAX => Some((b, n + 1)),
+            Some((b, n)) => {
+                push_run(&mut data, b, n);
+                Some((*block, 1))
+            },
+            None => Some((*block, 1)),
+        };
+    }
+    
+    if let Some((b, n)) = run {
+        push_run(&mut data, b, n);
+    }
+    
+    data
+}
+
+fn push_run(data: &mut Vec<u8>, block: BlockId, len: u16) {
+    data.extend_from_slice(&len.to_le_bytes());
+    data.push(block.value());
+}
+
+// Decode a run length encoded `BlockList`.
+fn decode(data: &[u8]) -> io::Result<BlockList> {
+    if !data.len().is_multiple_of(RUN_SIZE) {
+        return Err(invalid_data("truncated sector data"));
+    }
+    
+    let mut runs = data.chunks(RUN_SIZE).map(|run| {
+        let len = u16::from_le_bytes([run[0], run[1]]);
+        (len, BlockId::new(run[2]))
+    });
+    
+    let mut list = BlockList::new_air();
+    let mut current = (0, BlockId::AIR);
+    
+    // Visit the coords in the same order as the `BlockList` iterator.
+    for z in 0..SECTOR_SIZE {
+        for y in 0..SECTOR_SIZE {
+            for x in 0..SECTOR_SIZE {
+                while current.0 == 0 {
+                    current = match runs.next() {
+                        Some(run) => run,
+                        None => return Err(invalid_data("too few blocks in sector")),
+                    };
+                }
+                
+                current.0 -= 1;
+                list.set(SectorSpaceCoords::new(x as u8, y as u8, z as u8), current.1);
+            }
+        }
+    }
+    
+    if current.0 != 0 || runs.next().is_some() {
+        return Err(invalid_data("too many blocks in sector"));
+    }
+    
+    Ok(list)
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+    use super::*;
+    
+    // A list with many short runs of different blocks.
+    fn mixed() -> BlockList {
+        let mut blocks = BlockList::new_air();
+        for z in 0..SECTOR_SIZE as u8 {
+            for y in 0..SECTOR_SIZE as u8 {
+                for x in 0..SECTOR_SIZE as u8 {
+                    let id = (x / 3 + y * 2 + z) % 5;
+                    blocks.set(SectorSpaceCoords::new(x, y, z), BlockId::new(id));
+                }
+            }
+        }
+        
+        blocks
+    }
+    
+    fn assert_same(a: &BlockList, b: &BlockList) {
+        assert!(a.into_iter().map(|(_, b)| b).eq(b.into_iter().map(|(_, b)| b)));
+    }
+    
+    // Create an empty directory for a store.
+    fn store_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("region-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+    
+    fn table(dir: &Path) -> Vec<(u32, u32)> {
+        read_table(&mut File::open(dir.join("r.0.0.0.region")).unwrap()).unwrap()
+    }
+    
+    fn file_len(dir: &Path) -> u64 {
+        fs::metadata(dir.join("r.0.0.0.region")).unwrap().len()
+    }
+    
+    #[test]
+    fn round_trip_uniform() {
+        let blocks = BlockList::filled(BlockId::new(3));
+        let data = encode(&blocks);
+        assert_eq!(data.len(), RUN_SIZE);
+        assert_same(&decode(&data).unwrap(), &blocks);
+    }
+    
+    #[test]
+    fn round_trip_mixed() {
+        let blocks = mixed();
+        assert_same(&decode(&encode(&blocks)).unwrap(), &blocks);
+    }
+    
+    #[test]
+    fn split_negative() {
+        assert_eq!(split(0), (0, 0));
+        assert_eq!(split(REGION_SIZE - 1), (0, REGION_SIZE as usize - 1));
+        assert_eq!(split(REGION_SIZE), (1, 0));
+        assert_eq!(split(-1), (-1, REGION_SIZE as usize - 1));
+        assert_eq!(split(-REGION_SIZE), (-1, 0));
+        assert_eq!(split(-REGION_SIZE - 1), (-2, REGION_SIZE as usize - 1));
+    }
+    
+    #[test]
+    fn locate_negative() {
+        let size = REGION_SIZE as usize;
+        assert_eq!(locate((-1, 0, -1)), ((-1, 0, -1), size - 1 + (size - 1) * size * size));
+        assert_eq!(locate((-REGION_SIZE, -1, REGION_SIZE)), ((-1, -1, 1), (size - 1) * size));
+    }
+    
+    #[test]
+    fn decode_rejects_bad_data() {
+        let mut data = encode(&BlockList::new_air());
+        
+        let truncated = &data[..RUN_SIZE - 1];
+        assert_eq!(decode(truncated).err().unwrap().to_string(), "truncated sector data");
+        
+        let too_few = [1, 0, 0];
+        assert_eq!(decode(&too_few).err().unwrap().to_string(), "too few blocks in sector");
+        
+        data.extend_from_slice(&[1, 0, 0]);
+        assert_eq!(decode(&data).err().unwrap().to_string(), "too many blocks in sector");
+    
+    }
+    
+    #[test]
+    fn save_and_load() {
+        let dir = store_dir("save");
+        let store = RegionStore::new(&dir);
+        let header = HEADER_SIZE as u32;
+        let small = BlockList::filled(BlockId::new(1));
+        let large = mixed();
+        let large_len = encode(&large).len() as u32;
+        
+        assert!(store.load((0, 0, 0)).unwrap().is_none());
+        
+        store.save((0, 0, 0), &small).unwrap();
+        store.save((1, 0, 0), &small).unwrap();
+        assert_eq!(&table(&dir)[..2], &[(header, 3), (header + 3, 3)]);
+        
+        // A larger copy does not fit where the old copy was.
+        store.save((0, 0, 0), &large).unwrap();
+        assert_eq!(table(&dir)[0], (header + 6, large_len));
+        assert_eq!(file_len(&dir), (header + 6 + large_len) as u64);
+        assert_same(&store.load((0, 0, 0)).unwrap().unwrap(), &large);
+        
+        // The gap left by the old copy is used again.
+        store.save((2, 0, 0), &small).unwrap();
+        assert_eq!(table(&dir)[2], (header, 3));
+        
+        // The old copy is kept until the new copy is written,
+        // then the file is truncated after the last copy.
+        store.save((0, 0, 0), &small).unwrap();
+        assert_eq!(table(&dir)[0], (header + 6 + large_len, 3));
+        store.save((0, 0, 0), &small).unwrap();
+        assert_eq!(table(&dir)[0], (header + 6, 3));
+        assert_eq!(file_len(&dir), (header + 9) as u64);
+        
+        for &sector in &[(0, 0, 0), (1, 0, 0), (2, 0, 0)] {
+            assert_same(&store.load(sector).unwrap().unwrap(), &small);
+        }
+        
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,442 @@
+//! The kinds of blocks in the world, and their properties,
+//! loaded from a TOML data file.
+//!
+//! Each block is a table named after the block, with these keys:
+//!
+//! * `id`: the numeric id, from 0 to 255. Required.
+//! * `solid`: `true` or `false`. Solid blocks stop the player.
+//!   Defaults to `true`.
+//! * `fluid`: `true` or `false`. Rays pass through fluids, so
+//!   that the blocks in them can be picked. Fluids must not be
+//!   solid. Defaults to `false`.
+//! * `transparent`: `true` or `false`. Faces next to a transparent
+//!   block are drawn. Defaults to `false`.
+//! * `light`: the light the block emits, from 0 to 15.
+//!   Defaults to 0.
+//! * `pass`: how the block is drawn. `"opaque"` blocks hide what is
+//!   behind them, `"cutout"` blocks are see-through wherever their
+//!   texture is, and `"translucent"` blocks are blended with what is
+//!   behind them. Blocks that are not opaque must be transparent.
+//!   Defaults to `"opaque"`.
+//! * `shape`: `"cube"`, `"slab"`, `"stairs"`, `"fence"` or `"cross"`.
+//!   Blocks that are not cubes must be transparent. Defaults to
+//!   `"cube"`.
+//! * `texture`: the atlas tile for every face.
+//! * `sides`: the atlas tile for the four side faces.
+//! * `top`, `bottom`, `left`, `right`, `front`, `back`:
+//!   the atlas tile for one face.
+//!
+//! The most specific texture given for a face is used. A block
+//! with no textures at all is never drawn.
+//!
+//! The id 0 is reserved for air.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Read};
+use toml;
+use super::mesh_gen::{Face, FACES};
+use super::shape::BlockShape;
+use super::voxel::BlockId;
+
+/// The brightest light a block may emit.
+pub const MAX_LIGHT: u8 = 15;
+
+/// How a block is drawn. Terrain is drawn one pass after another,
+/// in the order of `RENDER_PASSES`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RenderPass {
+    /// The block hides everything behind it.
+    Opaque,
+    
+    /// The block is see-through wherever its texture is mostly
+    /// transparent, and hides everything behind it elsewhere.
+    Cutout,
+    
+    /// The block is blended with what is behind it, by the
+    /// transparency of its texture.
+    Translucent,
+}
+
+/// Every render pass, in the order in which they are drawn.
+pub const RENDER_PASSES: [RenderPass; 3] = [RenderPass::Opaque,
+                                            RenderPass::Cutout,
+                                            RenderPass::Translucent];
+
+/// The properties of one kind of block.
+#[derive(Clone, Debug)]
+pub struct BlockInfo {
+    name: String,
+    id: BlockId,
+    solid: bool,
+    fluid: bool,
+    transparent: bool,
+    light: u8,
+    pass: RenderPass,
+    shape: BlockShape,
+    textures: Option<[String; 6]>,
+}
+
+impl BlockInfo {
+    /// The name of the block.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    
+    /// The id under which the block is stored.
+    pub fn id(&self) -> BlockId {
+        self.id
+    }
+    
+    /// Determine if the block stops the player.
+    pub fn is_solid(&self) -> bool {
+        self.solid
+    }
+    
+    /// Determine if rays pass through the block.
+    pub fn is_fluid(&self) -> bool {
+        self.fluid
+    }
+    
+    /// Determine if the faces of neighboring blocks
+    /// can be seen through this block.
+    pub fn is_transparent(&self) -> bool {
+        self.transparent
+    }
+    
+    /// The light that the block emits.
+    pub fn light(&self) -> u8 {
+        self.light
+    }
+    
+    /// The pass in which the block is drawn.
+    pub fn pass(&self) -> RenderPass {
+        self.pass
+    }
+    
+    /// The shape of the block.
+    pub fn shape(&self) -> BlockShape {
+        self.shape
+    }
+    
+    /// Determine if the block must be drawn.
+    pub fn needs_rendering(&self) -> bool {
+        self.textures.is_some()
+    }
+    
+    /// The name of the atlas tile for one face of the block.
+    /// Blocks that are not drawn have no tiles.
+    pub fn texture(&self, face: Face) -> Option<&str> {
+        self.textures.as_ref().map(|textures| &textures[face as usize][..])
+    }
+}
+
+/// Every kind of block, looked up by id or by name.
+///
+/// Blocks with an id that the registry does not know, such as
+/// those saved before a block was removed from the data file,
+/// are treated as solid and opaque, and are drawn with the
+/// first tile of the atlas.
+pub struct BlockRegistry {
+    blocks: Vec<Option<BlockInfo>>,
+    names: HashMap<String, BlockId>,
+}
+
+impl BlockRegistry {
+    /// Read the registry from a data file.
+    /// # Errors
+    /// Fails if the file cannot be read, if it is malformed, if two
+    /// blocks share a name or an id, or if id 0 is not air.
+    pub fn load<R: Read>(mut data: R) -> io::Result<BlockRegistry> {
+        let mut text = String::new();
+        data.read_to_string(&mut text)?;
+        
+        // A name used twice is an error of the TOML itself.
+        let defs: BTreeMap<String, BlockDef> = toml::from_str(&text).map_err(|e| {
+            invalid_data(&e.to_string())
+        })?;
+        
+        let mut registry = BlockRegistry {
+            blocks: vec![None; u8::MAX as usize + 1],
+            names: HashMap::new(),
+        };
+        
+        for (name, def) in defs {
+            registry.insert(def.build(name)?)?;
+        }
+        
+        match registry.get(BlockId::AIR) {
+            Some(info) if info.name == "air" => Ok(registry),
+            _ => Err(invalid_data("id 0 must be the block named \"air\"")),
+        }
+    }
+    
+    /// Look up the properties of a block.
+    /// Returns `None` if no block has this id.
+    pub fn get(&self, id: BlockId) -> Option<&BlockInfo> {
+        self.blocks[id.value() as usize].as_ref()
+    }
+    
+    /// Find the id of the block with this name.
+    pub fn id(&self, name: &str) -> Option<BlockId> {
+        self.names.get(name).cloned()
+    }
+    
+    /// Iterate over every known block, in order of id.
+    pub fn iter(&self) -> impl Iterator<Item = &BlockInfo> {
+        self.blocks.iter().filter_map(|info| info.as_ref())
+    }
+    
+    /// Determine if the block stops the player.
+    pub fn is_solid(&self, id: BlockId) -> bool {
+        self.get(id).is_none_or(|info| info.is_solid())
+    }
+    
+    /// Determine if rays pass through the block.
+    pub fn is_fluid(&self, id: BlockId) -> bool {
+        self.get(id).is_some_and(|info| info.is_fluid())
+    }
+    
+    /// Determine if the faces of neighboring blocks
+    /// can be seen through the block.
+    pub fn is_transparent(&self, id: BlockId) -> bool {
+        self.get(id).is_some_and(|info| info.is_transparent())
+    }
+    
+    /// The light that the block emits.
+    pub fn light(&self, id: BlockId) -> u8 {
+        self.get(id).map_or(0, |info| info.light())
+    }
+    
+    /// The pass in which the block is drawn.
+    pub fn pass(&self, id: BlockId) -> RenderPass {
+        self.get(id).map_or(RenderPass::Opaque, |info| info.pass())
+    }
+    
+    /// The shape of the block.
+    pub fn shape(&self, id: BlockId) -> BlockShape {
+        self.get(id).map_or(BlockShape::Cube, |info| info.shape())
+    }
+    
+    /// Determine if the block must be drawn.
+    pub fn needs_rendering(&self, id: BlockId) -> bool {
+        self.get(id).is_none_or(|info| info.needs_rendering())
+    }
+    
+    // Add a block, checking that its id is unused.
+    fn insert(&mut self, info: BlockInfo) -> io::Result<()> {
+        let slot = info.id.value() as usize;
+        
+        if let Some(ref other) = self.blocks[slot] {
+            return Err(invalid_data(&format!("block {:?}: id {} is already used by {:?}",
+                                             info.name, slot, other.name)));
+        }
+        
+        self.names.insert(info.name.clone(), info.id);
+        self.blocks[slot] = Some(info);
+        
+        Ok(())
+    }
+}
+
+// A block as it is read from the data file.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct BlockDef {
+    id: Option<u8>,
+    solid: bool,
+    fluid: bool,
+    transparent: bool,
+    light: u8,
+    pass: RenderPass,
+    shape: BlockShape,
+    
+    // The tiles, from least to most specific.
+    texture: Option<String>,
+    sides: Option<String>,
+    top: Option<String>,
+    bottom: Option<String>,
+    left: Option<String>,
+    right: Option<String>,
+    front: Option<String>,
+    back: Option<String>,
+}
+
+impl Default for BlockDef {
+    fn default() -> BlockDef {
+        BlockDef {
+            id: None,
+            solid: true,
+            fluid: false,
+            transparent: false,
+            light: 0,
+            pass: RenderPass::Opaque,
+            shape: BlockShape::Cube,
+            texture: None,
+            sides: None,
+            top: None,
+            bottom: None,
+            left: None,
+            right: None,
+            front: None,
+            back: None,
+        }
+    }
+}
+
+impl BlockDef {
+    // The tile given for one face alone.
+    fn face(&self, face: Face) -> Option<&String> {
+        match face {
+            Face::Back => self.back.as_ref(),
+            Face::Front => self.front.as_ref(),
+            Face::Top => self.top.as_ref(),
+            Face::Bottom => self.bottom.as_ref(),
+            Face::Left => self.left.as_ref(),
+            Face::Right => self.right.as_ref(),
+        }
+    }
+    
+    fn build(self, name: String) -> io::Result<BlockInfo> {
+        let invalid = |msg: &str| invalid_data(&format!("block {:?}: {}", name, msg));
+        
+        if name.is_empty() {
+            return Err(invalid_data("block has no name"));
+        }
+        
+        let id = self.id.ok_or_else(|| invalid("it has no id"))?;
+        
+        if self.light > MAX_LIGHT {
+            return Err(invalid(&format!("light must be at most {}", MAX_LIGHT)));
+        }
+        
+        if self.fluid && self.solid {
+            return Err(invalid("it is a fluid, so it must not be solid"));
+        }
+        
+        // Faces behind a block that is not opaque must be drawn.
+        if self.pass != RenderPass::Opaque && !self.transparent {
+            return Err(invalid("it is not opaque, so it must be transparent"));
+        }
+        
+        // Light and the faces of neighbors show
+        // around blocks that are not cubes.
+        if self.shape != BlockShape::Cube && !self.transparent {
+            return Err(invalid("it is not a cube, so it must be transparent"));
+        }
+        
+        let any_texture = self.texture.is_some() || self.sides.is_some() ||
+                          FACES.iter().any(|&face| self.face(face).is_some());
+        
+        let textures = if any_texture {
+            let mut textures: [String; 6] = Default::default();
+            
+            for &face in &FACES {
+                let side = match face {
+                    Face::Top | Face::Bottom => None,
+                    _ => self.sides.as_ref(),
+                };
+                
+                let tile = self.face(face).or(side).or(self.texture.as_ref());
+                
+                textures[face as usize] = match tile {
+                    Some(tile) => tile.clone(),
+                    None => return Err(invalid(&format!("it has no texture for its {:?} face",
+                                                        face))),
+                };
+            }
+            
+            Some(textures)
+        } else {
+            None
+        };
+        
+        Ok(BlockInfo {
+            name,
+            id: BlockId::new(id),
+            solid: self.solid,
+            fluid: self.fluid,
+            transparent: self.transparent,
+            light: self.light,
+            pass: self.pass,
+            shape: self.shape,
+            textures,
+        })
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use super::*;
+    
+    fn error(data: &str) -> String {
+        match BlockRegistry::load(data.as_bytes()) {
+            Ok(_) => panic!("the registry should not load"),
+            Err(e) => e.to_string(),
+        }
+    }
+    
+    #[test]
+    fn data_file() {
+        let registry = BlockRegistry::load(File::open("data/blocks.toml").unwrap()).unwrap();
+        let water = registry.id("water").unwrap();
+        let grass = registry.get(registry.id("grass").unwrap()).unwrap();
+        
+        assert!(registry.is_fluid(water) && !registry.is_solid(water));
+        assert_eq!(registry.pass(water), RenderPass::Translucent);
+        assert_eq!(grass.texture(Face::Top), Some("grass"));
+        assert_eq!(grass.texture(Face::Bottom), Some("loam"));
+        assert_eq!(grass.texture(Face::Left), Some("grass_side"));
+        assert!(!registry.needs_rendering(BlockId::AIR));
+    }
+    
+    #[test]
+    fn defaults() {
+        let registry = BlockRegistry::load(&b"[air]\nid = 0\n\n[rock]\nid = 7\n"[..]).unwrap();
+        let rock = registry.id("rock").unwrap();
+        
+        assert_eq!(rock, BlockId::new(7));
+        assert!(registry.is_solid(rock) && !registry.is_fluid(rock));
+        assert!(!registry.is_transparent(rock));
+        assert_eq!(registry.light(rock), 0);
+        assert_eq!(registry.shape(rock), BlockShape::Cube);
+        assert!(!registry.needs_rendering(rock));
+    }
+    
+    #[test]
+    fn duplicate_name() {
+        assert!(error("[air]\nid = 0\n\n[air]\nid = 1\n").contains("air"));
+    }
+    
+    #[test]
+    fn duplicate_id() {
+        assert_eq!(error("[air]\nid = 0\n\n[rock]\nid = 0\n"),
+                   "block \"rock\": id 0 is already used by \"air\"");
+    }
+    
+    #[test]
+    fn unknown_property() {
+        assert!(error("[air]\nid = 0\ncolour = \"red\"\n").starts_with("unknown field `colour`"));
+    }
+    
+    #[test]
+    fn invalid_properties() {
+        assert!(error("[air]\nid = 0\nshape = \"sphere\"\n").starts_with("unknown variant"));
+        assert!(error("[air]\nid = 256\n").starts_with("invalid value: integer `256`"));
+        assert_eq!(error("[air]\n"), "block \"air\": it has no id");
+        assert_eq!(error("[air]\nid = 0\nlight = 16\n"),
+                   "block \"air\": light must be at most 15");
+        assert_eq!(error("[air]\nid = 0\nfluid = true\n"),
+                   "block \"air\": it is a fluid, so it must not be solid");
+        assert_eq!(error("[air]\nid = 0\npass = \"cutout\"\n"),
+                   "block \"air\": it is not opaque, so it must be transparent");
+        assert_eq!(error("[air]\nid = 0\ntop = \"grass\"\n"),
+                   "block \"air\": it has no texture for its Back face");
+        assert_eq!(error("[rock]\nid = 0\n"), "id 0 must be the block named \"air\"");
+    }
+}
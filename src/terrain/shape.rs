@@ -0,0 +1,92 @@
+//! The shapes of blocks, which are drawn and collided with.
+//!
+//! Every shape is made of boxes inside the unit cube of its block,
+//! except for crosses, which are two crossed quads with nothing to
+//! collide with.
+
+use super::mesh_gen::Face;
+
+/// A box inside a block, from `min` to `max` along each axis.
+/// The block is the unit cube from 0 to 1. The boxes of
+/// a shape never overlap.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BlockBox {
+    /// The corner of the box nearest to the origin of the block.
+    pub min: [f32; 3],
+    
+    /// The corner of the box farthest from the origin of the block.
+    pub max: [f32; 3],
+}
+
+const CUBE: [BlockBox; 1] = [
+    BlockBox { min: [0., 0., 0.], max: [1., 1., 1.] },
+];
+
+const SLAB: [BlockBox; 1] = [
+    BlockBox { min: [0., 0., 0.], max: [1., 0.5, 1.] },
+];
+
+const STAIRS: [BlockBox; 2] = [
+    BlockBox { min: [0., 0., 0.], max: [1., 0.5, 1.] },
+    BlockBox { min: [0., 0.5, 0.], max: [1., 1., 0.5] },
+];
+
+const FENCE: [BlockBox; 1] = [
+    BlockBox { min: [0.375, 0., 0.375], max: [0.625, 1., 0.625] },
+];
+
+/// The shape of a block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlockShape {
+    /// The whole block.
+    Cube,
+    
+    /// The bottom half of the block.
+    Slab,
+    
+    /// A slab with a step on its back half, which rises
+    /// toward -Z.
+    Stairs,
+    
+    /// A thin post in the middle of the block.
+    Fence,
+    
+    /// Two quads crossed along the diagonals of the block,
+    /// for plants such as grass and flowers.
+    Cross,
+}
+
+impl BlockShape {
+    /// The boxes that make up the shape. They are both drawn and
+    /// collided with. Crosses have no boxes.
+    pub fn boxes(&self) -> &'static [BlockBox] {
+        use self::BlockShape::*;
+        
+        match *self {
+            Cube => &CUBE,
+            Slab => &SLAB,
+            Stairs => &STAIRS,
+            Fence => &FENCE,
+            Cross => &[],
+        }
+    }
+    
+    /// Determine if the boxes of the shape cover the whole side of
+    /// the block on `face`, hiding the face of the block next to it.
+    pub fn covers(&self, face: Face) -> bool {
+        let normal = face.normal();
+        let normal = [normal.0, normal.1, normal.2];
+        let axis = normal.iter().position(|&n| n != 0).unwrap();
+        
+        // The boxes do not overlap, so the side is covered
+        // when their areas on it add up to the whole side.
+        let area: f32 = self.boxes().iter().filter(|b| {
+            if normal[axis] > 0 { b.max[axis] >= 1. } else { b.min[axis] <= 0. }
+        }).map(|b| {
+            (0..3).filter(|&a| a != axis).map(|a| b.max[a] - b.min[a]).product::<f32>()
+        }).sum();
+        
+        area >= 1.
+    }
+}
@@ -0,0 +1,141 @@
+//! Simple world generators, for testing rendering and collision.
+
+use super::SECTOR_SIZE;
+use super::registry::BlockRegistry;
+use super::voxel::{BlockId, BlockList, SectorSpaceCoords};
+use super::world_gen::WorldGenerator;
+
+/// A flat world made of layers of blocks.
+#[derive(Clone)]
+pub struct Superflat {
+    // The block at every height, from y = 0 up.
+    column: Vec<BlockId>,
+}
+
+impl Superflat {
+    /// Create a `Superflat` world from layers of blocks, listed
+    /// from the bottom up along with their thickness. The bottom
+    /// layer starts at y = 0, and below it there is only air.
+    pub fn new(layers: &[(BlockId, u32)]) -> Superflat {
+        let mut column = Vec::new();
+        
+        for &(block, thickness) in layers {
+            for _ in 0..thickness {
+                column.push(block);
+            }
+        }
+        
+        Superflat {
+            column,
+        }
+    }
+    
+    /// Create a `Superflat` world of limestone,
+    /// under loam, under a layer of grass.
+    /// # Panics
+    /// Panics if `registry` lacks one of these blocks.
+    pub fn classic(registry: &BlockRegistry) -> Superflat {
+        let block = |name| registry.id(name).unwrap_or_else(|| {
+            panic!("superflat worlds need a block named {:?}", name)
+        });
+        
+        Superflat::new(&[(block("limestone"), 4),
+                         (block("loam"), 3),
+                         (block("grass"), 1)])
+    }
+}
+
+impl WorldGenerator for Superflat {
+    fn generate(&self, sector: (i32, i32, i32)) -> BlockList {
+        let mut list = BlockList::new_air();
+        
+        for y in 0..SECTOR_SIZE {
+            let world_y = sector.1 * SECTOR_SIZE as i32 + y as i32;
+            if world_y < 0 || world_y as usize >= self.column.len() {
+                continue;
+            }
+            
+            for z in 0..SECTOR_SIZE {
+                for x in 0..SECTOR_SIZE {
+                    list.set(SectorSpaceCoords::new(x as u8, y as u8, z as u8),
+                             self.column[world_y as usize]);
+                }
+            }
+        }
+        
+        list
+    }
+    
+    fn height_at(&self, _x: i32, _z: i32) -> i32 {
+        self.column.len() as i32
+    }
+}
+
+/// A world that shows off every block: a checkerboard floor
+/// at y = 0, with one of every drawn block standing on it in
+/// a row along the x axis.
+#[derive(Clone)]
+pub struct DebugWorld {
+    floor: (BlockId, BlockId),
+    blocks: Vec<BlockId>,
+}
+
+impl DebugWorld {
+    /// Create a `DebugWorld` with the blocks in `registry`.
+    /// # Panics
+    /// Panics if no block in `registry` is drawn.
+    pub fn new(registry: &BlockRegistry) -> DebugWorld {
+        let blocks: Vec<_> = registry.iter()
+                                     .filter(|info| info.needs_rendering())
+                                     .map(|info| info.id())
+                                     .collect();
+        
+        assert!(!blocks.is_empty(), "the debug world needs a block that is drawn");
+        
+        DebugWorld {
+            floor: (blocks[0], blocks[1 % blocks.len()]),
+            blocks,
+        }
+    }
+}
+
+impl WorldGenerator for DebugWorld {
+    fn generate(&self, sector: (i32, i32, i32)) -> BlockList {
+        let mut list = BlockList::new_air();
+        if sector.1 != 0 {
+            return list;
+        }
+        
+        let size = SECTOR_SIZE as i32;
+        
+        for z in 0..size {
+            for x in 0..size {
+                let (world_x, world_z) = (sector.0 * size + x, sector.2 * size + z);
+                
+                let block = if (world_x + world_z) % 2 == 0 {
+                    self.floor.0
+                } else {
+                    self.floor.1
+                };
+                
+                list.set(SectorSpaceCoords::new(x as u8, 0, z as u8), block);
+            }
+        }
+        
+        // The blocks are two apart, so that
+        // every face can be seen.
+        for (i, &block) in self.blocks.iter().enumerate() {
+            let (x, z) = (2 * i as i32 - sector.0 * size, 2 - sector.2 * size);
+            
+            if x >= 0 && x < size && z >= 0 && z < size {
+                list.set(SectorSpaceCoords::new(x as u8, 1, z as u8), block);
+            }
+        }
+        
+        list
+    }
+    
+    fn height_at(&self, _x: i32, _z: i32) -> i32 {
+        1
+    }
+}
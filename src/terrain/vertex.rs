@@ -0,0 +1,83 @@
+//! The packed format of terrain vertices.
+//!
+//! A vertex is two words. The first holds the position in the
+//! sector, in sixteenths of a block, with x in bits 0 to 9, y in
+//! bits 10 to 19, and z in bits 20 to 29. The second holds the face
+//! in bits 0 to 2, the ambient occlusion in bits 3 and 4, the light
+//! in bits 8 to 15, and the index of the atlas tile in bits 16 to 31.
+//!
+//! Texture coordinates are not stored. The vertex shader finds them
+//! from the position and the face, so that the tile lines up across
+//! blocks and repeats once per block. `shaders/vs.glsl` decodes this
+//! format, and must be changed along with it.
+
+use super::Vertex;
+
+/// The number of steps a block is divided into along each
+/// axis. Positions are rounded to the nearest step.
+pub const POSITION_STEPS: f32 = 16.;
+
+/// The face number of the quads of crosses, which lie along the
+/// diagonals of a block rather than on one of its faces.
+pub const CROSS_FACE: u32 = 6;
+
+const POSITION_BITS: u32 = 10;
+const POSITION_MASK: u32 = (1 << POSITION_BITS) - 1;
+
+/// A terrain vertex before it is packed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VertexData {
+    /// The position in the sector, from 0 to `SECTOR_SIZE`
+    /// along each axis.
+    pub pos: [f32; 3],
+    
+    /// The face the vertex belongs to, as a `Face`,
+    /// or `CROSS_FACE`.
+    pub face: u32,
+    
+    /// The ambient occlusion, from 0, fully occluded,
+    /// to 3, not occluded at all.
+    pub occlusion: u32,
+    
+    /// The light, with the sky light in bits 4 to 7
+    /// and the block light in bits 0 to 3.
+    pub light: u32,
+    
+    /// The index of the atlas tile.
+    pub tile: u32,
+}
+
+impl VertexData {
+    /// Pack the vertex into the format that is uploaded.
+    /// Panics if a field does not fit in its bits.
+    pub fn pack(&self) -> Vertex {
+        assert!(self.face <= CROSS_FACE && self.occlusion <= 3 &&
+                self.light <= 0xff && self.tile <= 0xffff);
+        
+        let mut pos = 0;
+        for (axis, &p) in self.pos.iter().enumerate() {
+            let steps = (p * POSITION_STEPS).round() as u32;
+            assert!(p >= 0. && steps <= POSITION_MASK);
+            
+            pos |= steps << (axis as u32 * POSITION_BITS);
+        }
+        
+        (pos, self.face | self.occlusion << 3 | self.light << 8 | self.tile << 16)
+    }
+    
+    /// Read a packed vertex.
+    pub fn unpack(vertex: Vertex) -> VertexData {
+        let (pos, attributes) = vertex;
+        let coord = |axis: u32| {
+            (pos >> (axis * POSITION_BITS) & POSITION_MASK) as f32 / POSITION_STEPS
+        };
+        
+        VertexData {
+            pos: [coord(0), coord(1), coord(2)],
+            face: attributes & 0x7,
+            occlusion: attributes >> 3 & 0x3,
+            light: attributes >> 8 & 0xff,
+            tile: attributes >> 16,
+        }
+    }
+}
@@ -1,8 +1,13 @@
 //! A module for managing the voxels in the world.
 
-use std::{iter, slice};
+use std::mem;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use luminance::tess::{Mode, Tess, TessVertices};
-use super::{mesh_gen, Vertex, SECTOR_SIZE};
+use super::{Vertex, SECTOR_SIZE};
+use super::light::LightList;
+use super::mesh_gen::{Mesh, PassMeshes};
+use super::registry::{BlockRegistry, RenderPass};
 use maths::Translation;
 use model::Model;
 use resources::Resources;
@@ -13,34 +18,34 @@ use resources::Resources;
 //  ([ 0.5, -0.5, 0.0], [1.0, 1.0]),
 //];
 
-/// A block in the world.
-#[derive(Clone, Copy, Debug)]
-pub enum Block {
-    Air,
-    Limestone,
-    Loam,
-    Grass,
-    Tree,
-    Leaves,
-}
+/// A block in the world, stored as the compact numeric id
+/// under which the `BlockRegistry` describes it.
+/// The id 0 is always air.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BlockId(u8);
 
-impl Block {
-    /// Determine if the block is air.
-    pub fn is_air(&self) -> bool {
-        match *self {
-            Block::Air => true,
-            _ => false,
-        }
+impl BlockId {
+    /// The block that fills empty space.
+    pub const AIR: BlockId = BlockId(0);
+    
+    /// Create a block id from its numeric value.
+    pub fn new(id: u8) -> BlockId {
+        BlockId(id)
     }
     
-    /// Determine if the block must be drawn.
-    pub fn needs_rendering(&self) -> bool {
-        !self.is_air()
+    /// The numeric value of the id, used when saving to disk.
+    pub fn value(&self) -> u8 {
+        self.0
+    }
+    
+    /// Determine if the block is air.
+    pub fn is_air(&self) -> bool {
+        *self == BlockId::AIR
     }
 }
 
-// The length of an array of blocks for a sector.
-const SECTOR_LEN: usize = SECTOR_SIZE * SECTOR_SIZE * SECTOR_SIZE;
+/// The length of an array of blocks for a sector.
+pub const SECTOR_LEN: usize = SECTOR_SIZE * SECTOR_SIZE * SECTOR_SIZE;
 
 /// The type of sector space coordinates.
 #[derive(Clone, Copy, Debug)]
@@ -126,85 +131,261 @@ impl SectorSpaceCoords {
         }
     }
     
+    /// The position of these coords in an array
+    /// of `SECTOR_LEN` blocks.
+    pub fn index(&self) -> usize {
+        let (x, y, z) = (self.x as usize, self.y as usize, self.z as usize);
+        
+        x + y * SECTOR_SIZE + z * SECTOR_SIZE * SECTOR_SIZE
+    }
+    
     pub fn x(&self) -> u8 { self.x }
     pub fn y(&self) -> u8 { self.y }
     pub fn z(&self) -> u8 { self.z }
 }
 
-/// The array structure of blocks in a `Sector`.
-pub struct BlockList([Block; SECTOR_LEN]);
+/// The structure of blocks in a `Sector`.
+/// A sector made of only one kind of block stores just that block.
+/// Otherwise, the sector stores a palette of the blocks it contains,
+/// and each position holds a packed index into that palette.
+#[derive(Clone)]
+pub struct BlockList(Storage);
+
+// The representation of a `BlockList`.
+#[derive(Clone)]
+enum Storage {
+    Uniform(BlockId),
+    Paletted(Palette),
+}
 
 impl BlockList {
     /// Create a new `BlockList`, consuming the array
-    /// of `Block`s.
-    pub fn new(blocks: [Block; SECTOR_LEN]) -> BlockList {
-        BlockList(blocks)
+    /// of `BlockId`s.
+    pub fn new(blocks: [BlockId; SECTOR_LEN]) -> BlockList {
+        let mut list = Self::filled(blocks[0]);
+        
+        for (i, block) in blocks.iter().enumerate() {
+            list.set_index(i, *block);
+        }
+        
+        list
     }
     
-    /// Create a new `BlockList` fulled with air.
+    /// Create a new `BlockList` filled with one kind of block.
+    pub fn filled(block: BlockId) -> BlockList {
+        BlockList(Storage::Uniform(block))
+    }
+    
+    /// Create a new `BlockList` filled with air.
     pub fn new_air() -> BlockList {
-        BlockList([Block::Air; SECTOR_LEN])
+        Self::filled(BlockId::AIR)
     }
 
     /// Look at the block at a specific position in sector coords.
-    pub fn get(&self, pos: SectorSpaceCoords) -> &Block {
-        &self.0[Self::index(pos)]
+    pub fn get(&self, pos: SectorSpaceCoords) -> &BlockId {
+        self.get_index(pos.index())
     }
     
     /// Set a block at a specific position in sector coords.
-    pub fn set(&mut self, pos: SectorSpaceCoords, block: Block) {
-        self.0[Self::index(pos)] = block;
+    pub fn set(&mut self, pos: SectorSpaceCoords, block: BlockId) {
+        self.set_index(pos.index(), block);
     }
     
-    /// Determine if all blocks in the `BlockList` are air.
-    pub fn needs_rendering(&self) -> bool {
-        for i in self.0.iter() {
-            if i.needs_rendering() {
-                return true;
-            }
+    /// Determine if any block in the `BlockList` must be drawn.
+    pub fn needs_rendering(&self, registry: &BlockRegistry) -> bool {
+        match self.0 {
+            Storage::Uniform(ref block) => registry.needs_rendering(*block),
+            Storage::Paletted(ref palette) => palette.needs_rendering(registry),
         }
+    }
+    
+    // Look at the block at an internal index.
+    fn get_index(&self, index: usize) -> &BlockId {
+        match self.0 {
+            Storage::Uniform(ref block) => block,
+            Storage::Paletted(ref palette) => palette.get(index),
+        }
+    }
+    
+    // Set the block at an internal index, switching
+    // between representations as needed.
+    fn set_index(&mut self, index: usize, block: BlockId) {
+        let uniform = match self.0 {
+            Storage::Uniform(current) if current == block => return,
+            Storage::Uniform(current) => {
+                let mut palette = Palette::filled(current);
+                palette.set(index, block);
+                
+                self.0 = Storage::Paletted(palette);
+                return;
+            },
+            Storage::Paletted(ref mut palette) => {
+                palette.set(index, block);
+                palette.is_uniform()
+            },
+        };
         
-        false
+        if uniform {
+            self.0 = Storage::Uniform(block);
+        }
+    }
+}
+
+// The number of bits in a word of packed palette indices.
+const WORD_BITS: usize = 64;
+
+// The palette and packed indices of a `BlockList`
+// that contains more than one kind of block.
+#[derive(Clone)]
+struct Palette {
+    // The blocks that the indices refer to.
+    blocks: Vec<BlockId>,
+    
+    // How many positions use each entry of `blocks`.
+    // Entries that are no longer used are recycled.
+    counts: Vec<u16>,
+    
+    // The width of one index. Always a power of two,
+    // so that indices never straddle two words.
+    bits: usize,
+    
+    // The packed indices.
+    words: Vec<u64>,
+}
+
+impl Palette {
+    // Create a palette in which every position is `block`.
+    fn filled(block: BlockId) -> Palette {
+        Palette {
+            blocks: vec![block],
+            counts: vec![SECTOR_LEN as u16],
+            bits: 1,
+            words: vec![0; SECTOR_LEN / WORD_BITS],
+        }
     }
     
-    // Determines the internal index of sector coords.
-    fn index(pos: SectorSpaceCoords) -> usize {
-        let (x, y, z) = (pos.x() as usize, pos.y() as usize, pos.z() as usize);
+    fn get(&self, index: usize) -> &BlockId {
+        &self.blocks[self.entry(index)]
+    }
+    
+    fn set(&mut self, index: usize, block: BlockId) {
+        let old = self.entry(index);
+        if self.blocks[old] == block {
+            return;
+        }
         
-        x + y * SECTOR_SIZE + z * SECTOR_SIZE * SECTOR_SIZE
+        let new = self.find_or_insert(block);
+        
+        self.counts[old] -= 1;
+        self.counts[new] += 1;
+        self.set_entry(index, new);
+    }
+    
+    // Determine if a single block fills every position.
+    fn is_uniform(&self) -> bool {
+        self.counts.iter().any(|&c| c as usize == SECTOR_LEN)
+    }
+    
+    fn needs_rendering(&self, registry: &BlockRegistry) -> bool {
+        self.blocks.iter()
+                   .zip(&self.counts)
+                   .any(|(block, &count)| count > 0 && registry.needs_rendering(*block))
+    }
+    
+    // Find the palette entry for a block, adding one
+    // (and widening the indices) if it is missing.
+    fn find_or_insert(&mut self, block: BlockId) -> usize {
+        if let Some(entry) = self.blocks.iter().position(|b| *b == block) {
+            return entry;
+        }
+        
+        if let Some(entry) = self.counts.iter().position(|&c| c == 0) {
+            self.blocks[entry] = block;
+            return entry;
+        }
+        
+        self.blocks.push(block);
+        self.counts.push(0);
+        
+        if self.blocks.len() > 1 << self.bits {
+            self.widen();
+        }
+        
+        self.blocks.len() - 1
+    }
+    
+    // Double the width of every index.
+    fn widen(&mut self) {
+        let old_bits = self.bits;
+        let old_words = mem::take(&mut self.words);
+        
+        self.bits *= 2;
+        self.words = vec![0; SECTOR_LEN * self.bits / WORD_BITS];
+        
+        for i in 0..SECTOR_LEN {
+            self.set_entry(i, unpack(&old_words, old_bits, i));
+        }
+    }
+    
+    // The palette entry used at an index.
+    fn entry(&self, index: usize) -> usize {
+        unpack(&self.words, self.bits, index)
     }
+    
+    fn set_entry(&mut self, index: usize, entry: usize) {
+        let per_word = WORD_BITS / self.bits;
+        let shift = (index % per_word) * self.bits;
+        let mask = ((1 << self.bits) - 1) << shift;
+        
+        let word = &mut self.words[index / per_word];
+        *word = (*word & !mask) | ((entry as u64) << shift);
+    }
+}
+
+// Read one index of width `bits` from packed words.
+fn unpack(words: &[u64], bits: usize, index: usize) -> usize {
+    let per_word = WORD_BITS / bits;
+    let shift = (index % per_word) * bits;
+    let mask = (1 << bits) - 1;
+    
+    ((words[index / per_word] >> shift) & mask) as usize
 }
 
 /// An iterator over a BlockList.
-pub struct BlockListIter<'a>(iter::Enumerate<slice::Iter<'a, Block>>);
+pub struct BlockListIter<'a> {
+    list: &'a BlockList,
+    index: usize,
+}
 
-type BlockListIterItem<'a> = (SectorSpaceCoords, &'a Block);
+type BlockListIterItem<'a> = (SectorSpaceCoords, &'a BlockId);
 
 impl<'a> Iterator for BlockListIter<'a> {
     type Item = BlockListIterItem<'a>;
     
     fn next(&mut self) -> Option<Self::Item> {
-        match self.0.next() {
-            Some(i) => {
-                let mut total = i.0;
-                
-                let z = total / (SECTOR_SIZE * SECTOR_SIZE);
-                total -= z * SECTOR_SIZE * SECTOR_SIZE;
-                let z = z as u8;
-                
-                let y = total / SECTOR_SIZE;
-                total -= y * SECTOR_SIZE;
-                let y = y as u8;
-                
-                let x = total;
-                let x = x as u8;
-                
-                //println!("x: {}, y: {}, z: {}", x, y, z);
-                
-                Some((SectorSpaceCoords::new(x, y, z), i.1))
-            },
-            None => None,
+        if self.index >= SECTOR_LEN {
+            return None;
         }
+        
+        let mut total = self.index;
+        
+        let z = total / (SECTOR_SIZE * SECTOR_SIZE);
+        total -= z * SECTOR_SIZE * SECTOR_SIZE;
+        let z = z as u8;
+        
+        let y = total / SECTOR_SIZE;
+        total -= y * SECTOR_SIZE;
+        let y = y as u8;
+        
+        let x = total;
+        let x = x as u8;
+        
+        //println!("x: {}, y: {}, z: {}", x, y, z);
+        
+        let block = self.list.get_index(self.index);
+        self.index += 1;
+        
+        Some((SectorSpaceCoords::new(x, y, z), block))
     }
 }
 
@@ -213,65 +394,169 @@ impl<'a> IntoIterator for &'a BlockList {
     type IntoIter = BlockListIter<'a>;
     
     fn into_iter(self) -> BlockListIter<'a> {
-        BlockListIter(self.0.iter().enumerate())
+        BlockListIter {
+            list: self,
+            index: 0,
+        }
     }
 }
 
-/// Holds references to all adjacent sectors.
-pub struct AdjacentSectors<'a> {
-    pub back: &'a Sector,
-    pub front: &'a Sector,
-    pub top: &'a Sector,
-    pub bottom: &'a Sector,
-    pub left: &'a Sector,
-    pub right: &'a Sector,
+/// Holds references to the data of all adjacent sectors.
+pub struct Adjacent<'a, T: 'a> {
+    pub back: &'a T,
+    pub front: &'a T,
+    pub top: &'a T,
+    pub bottom: &'a T,
+    pub left: &'a T,
+    pub right: &'a T,
+    
+    // The sectors that share only an edge or a corner,
+    // indexed by `diagonal_index`, if they were added.
+    diagonals: [Option<&'a T>; 27],
 }
 
-impl<'a> AdjacentSectors<'a> {
+impl<'a, T> Adjacent<'a, T> {
     /// Create a new structure from all 6 neighbors.
-    pub fn new(back: &'a Sector,
-               front: &'a Sector,
-               top: &'a Sector,
-               bottom: &'a Sector,
-               left: &'a Sector,
-               right: &'a Sector) -> AdjacentSectors<'a> {
-        AdjacentSectors {
+    pub fn new(back: &'a T,
+               front: &'a T,
+               top: &'a T,
+               bottom: &'a T,
+               left: &'a T,
+               right: &'a T) -> Adjacent<'a, T> {
+        Adjacent {
             back,
             front,
             top,
             bottom,
             left,
             right,
+            diagonals: [None; 27],
         }
     }
+    
+    /// Add the neighbor at `offset`, one of `DIAGONALS`,
+    /// which shares only an edge or a corner.
+    pub fn set_diagonal(&mut self, offset: (i32, i32, i32), data: &'a T) {
+        self.diagonals[diagonal_index(offset)] = Some(data);
+    }
+    
+    /// Get the neighbor at `offset`, one of `DIAGONALS`,
+    /// if it was added.
+    pub fn diagonal(&self, offset: (i32, i32, i32)) -> Option<&'a T> {
+        self.diagonals[diagonal_index(offset)]
+    }
+}
+
+/// The offsets of the 12 neighbors that share an edge with
+/// a sector, and the 8 that share a corner.
+pub const DIAGONALS: [(i32, i32, i32); 20] = [
+    (-1, -1, 0), (1, -1, 0), (-1, 1, 0), (1, 1, 0),
+    (-1, 0, -1), (1, 0, -1), (-1, 0, 1), (1, 0, 1),
+    (0, -1, -1), (0, 1, -1), (0, -1, 1), (0, 1, 1),
+    
+    (-1, -1, -1), (1, -1, -1), (-1, 1, -1), (1, 1, -1),
+    (-1, -1, 1), (1, -1, 1), (-1, 1, 1), (1, 1, 1),
+];
+
+fn diagonal_index(offset: (i32, i32, i32)) -> usize {
+    ((offset.0 + 1) + (offset.1 + 1) * 3 + (offset.2 + 1) * 9) as usize
+}
+
+/// Holds references to the blocks of all adjacent sectors.
+pub type AdjacentSectors<'a> = Adjacent<'a, BlockList>;
+
+/// Holds references to the light of all adjacent sectors.
+pub type AdjacentLight<'a> = Adjacent<'a, LightList>;
+
+// The source of sector revisions. Revisions are never reused,
+// even by a sector that is unloaded and then loaded again.
+static NEXT_REVISION: AtomicUsize = AtomicUsize::new(0);
+
+fn next_revision() -> usize {
+    NEXT_REVISION.fetch_add(1, Ordering::Relaxed)
 }
 
+/// The models of a sector, with one for each `RenderPass`,
+/// indexed by the pass. Passes without any faces have no model.
+pub type SectorModels = [Option<Model<Vertex>>; 3];
+
 /// An individual "chunk" of the world.
 pub struct Sector {
-    blocks: BlockList,
-    model: Option<Model<Vertex>>,
+    blocks: Arc<BlockList>,
+    light: Arc<LightList>,
+    models: Option<SectorModels>,
+    saved: bool,
+    dirty: bool,
+    
+    // Whether the sector is above the surface of its column,
+    // so that nothing above it blocks the sky.
+    open_sky: bool,
+    
+    // Changes whenever the `Model` becomes out of date.
+    revision: usize,
+    
+    // The revision for which a mesh is being built.
+    meshing: Option<usize>,
 }
 
 impl Sector {
-    /// Create a sector.
-    pub fn new(blocks: BlockList) -> Sector {
-            //let blocks = BlockList([Block::Loam; SECTOR_LEN]);
-
+    /// Create a sector with its blocks and their light. The
+    /// sector needs no saving until it changes, as its blocks
+    /// are either on disk or made again by the generator.
+    pub fn new(blocks: BlockList, light: LightList) -> Sector {
         Sector {
-            blocks,
-            model: None,
+            blocks: Arc::new(blocks),
+            light: Arc::new(light),
+            models: None,
+            saved: true,
+            dirty: false,
+            open_sky: false,
+            revision: next_revision(),
+            meshing: None,
         }
     }
     
-    /// Return an immutable reference to this sector's `Model`.
-    /// The model may not exist, in which case `None` is returned.
-    pub fn model(&self) -> Option<&Model<Vertex>> {
-        self.model.as_ref()
+    /// Determine if the blocks on disk match this sector.
+    pub fn is_saved(&self) -> bool {
+        self.saved
+    }
+    
+    /// Record that the blocks on disk match this sector.
+    pub fn mark_saved(&mut self) {
+        self.saved = true;
+    }
+    
+    /// Determine if the sector is above the surface, so that
+    /// sky light enters it from above even when the sector
+    /// above it is not loaded.
+    pub fn is_open_sky(&self) -> bool {
+        self.open_sky
+    }
+    
+    /// Record that the sector is above the surface.
+    pub fn mark_open_sky(&mut self) {
+        self.open_sky = true;
     }
     
-    /// Set the `Sector`'s `Model`.
-    pub fn set_model(&mut self, model: Option<Model<Vertex>>) {
-        self.model = model;
+    /// Determine if this sector's models have been created,
+    /// even if some passes have no model.
+    pub fn has_models(&self) -> bool {
+        self.models.is_some()
+    }
+    
+    /// Return an immutable reference to this sector's `Model`
+    /// for one pass. The model may not exist, in which case
+    /// `None` is returned.
+    pub fn model(&self, pass: RenderPass) -> Option<&Model<Vertex>> {
+        self.models.as_ref().and_then(|models| models[pass as usize].as_ref())
+    }
+    
+    /// Set the `Sector`'s models.
+    /// The sector is no longer dirty afterward.
+    pub fn set_models(&mut self, models: Option<SectorModels>) {
+        self.models = models;
+        self.dirty = false;
+        self.meshing = None;
     }
     
     /// Return this sector's `BlockList`.
@@ -279,24 +564,198 @@ impl Sector {
         &self.blocks
     }
     
-    /// Create the `Model` for the `Sector`.
-    pub fn create_model(&self, resources: &Resources, pos: (i32, i32, i32),
-                  adjacent: &AdjacentSectors) -> Option<Model<Vertex>> {
-        if self.blocks.needs_rendering() {
-            let terrain_tex = resources.terrain_tex();
+    /// Share this sector's `BlockList`. Later changes
+    /// to the sector do not affect the snapshot.
+    pub fn snapshot(&self) -> Arc<BlockList> {
+        self.blocks.clone()
+    }
+    
+    /// Return the light of this sector's blocks.
+    pub fn light(&self) -> &LightList {
+        &self.light
+    }
+    
+    /// Change the light of this sector's blocks. The
+    /// model is not rebuilt until the sector is dirty.
+    pub fn light_mut(&mut self) -> &mut LightList {
+        Arc::make_mut(&mut self.light)
+    }
+    
+    /// Share the light of this sector's blocks. Later
+    /// changes to the light do not affect the snapshot.
+    pub fn light_snapshot(&self) -> Arc<LightList> {
+        self.light.clone()
+    }
+    
+    /// Change a block in this sector. The sector becomes
+    /// dirty and must be saved again.
+    pub fn set_block(&mut self, pos: SectorSpaceCoords, block: BlockId) {
+        Arc::make_mut(&mut self.blocks).set(pos, block);
+        self.saved = false;
+        self.mark_dirty();
+    }
+    
+    /// Determine if the `Model` must be recreated
+    /// because the blocks have changed.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+    
+    /// Record that the `Model` must be recreated.
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+        self.revision = next_revision();
+    }
+    
+    /// The revision of the sector. It changes every
+    /// time that the sector becomes dirty.
+    pub fn revision(&self) -> usize {
+        self.revision
+    }
+    
+    /// Determine if a mesh of the current revision
+    /// is being built.
+    pub fn is_meshing(&self) -> bool {
+        self.meshing == Some(self.revision)
+    }
+    
+    /// Record that a mesh of the current revision
+    /// is being built.
+    pub fn mark_meshing(&mut self) {
+        self.meshing = Some(self.revision);
+    }
+    
+    /// Create the models for the `Sector` by uploading
+    /// its mesh for each pass.
+    pub fn create_models(&self, resources: &Resources, pos: (i32, i32, i32),
+                         meshes: &PassMeshes) -> SectorModels {
+        let translation = Translation::new((pos.0 * SECTOR_SIZE as i32) as f32,
+                                           (pos.1 * SECTOR_SIZE as i32) as f32,
+                                           (pos.2 * SECTOR_SIZE as i32) as f32);
+        
+        //println!("translation: {:?}", translation);
+        
+        let create = |mesh: &Mesh| if mesh.is_empty() {
+            None
+        } else {
+            let tess = Tess::new(Mode::Triangle, TessVertices::Fill(&mesh.vertices),
+                                 Some(&mesh.indices[..]));
             
-            let vertices = mesh_gen::generate_block_vertices(&self.blocks, adjacent, &terrain_tex.1);
-            let tess = Tess::new(Mode::Triangle, TessVertices::Fill(&vertices), None);
+            Some(Model::with_translation(tess, resources.terrain_tex(), translation.clone()))
+        };
+        
+        [create(&meshes[0]), create(&meshes[1]), create(&meshes[2])]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    // The position of an internal index.
+    fn coords(index: usize) -> SectorSpaceCoords {
+        SectorSpaceCoords::new((index % SECTOR_SIZE) as u8,
+                               (index / SECTOR_SIZE % SECTOR_SIZE) as u8,
+                               (index / (SECTOR_SIZE * SECTOR_SIZE)) as u8)
+    }
+    
+    // The width of the palette indices, if the list has a palette.
+    fn bits(list: &BlockList) -> Option<usize> {
+        match list.0 {
+            Storage::Uniform(_) => None,
+            Storage::Paletted(ref palette) => Some(palette.bits),
+        }
+    }
+    
+    fn assert_blocks(list: &BlockList, expected: &[BlockId]) {
+        for (i, block) in expected.iter().enumerate() {
+            assert_eq!(list.get(coords(i)), block, "at index {}", i);
+        }
+    }
+    
+    #[test]
+    fn uniform_becomes_palette() {
+        let stone = BlockId::new(1);
+        let pos = coords(100);
+        let mut list = BlockList::filled(stone);
+        
+        list.set(pos, stone);
+        assert_eq!(bits(&list), None);
+        
+        list.set(pos, BlockId::AIR);
+        assert_eq!(bits(&list), Some(1));
+        assert_eq!(*list.get(pos), BlockId::AIR);
+        assert_eq!(*list.get(coords(101)), stone);
+        
+        // Back to a single kind of block.
+        list.set(pos, stone);
+        assert_eq!(bits(&list), None);
+        assert_eq!(*list.get(pos), stone);
+    }
+    
+    #[test]
+    fn indices_widen() {
+        let mut list = BlockList::new_air();
+        let mut expected = vec![BlockId::AIR; SECTOR_LEN];
+        
+        // Add one more kind of block at a time, spread over the list.
+        for id in 1..18u8 {
+            for i in (id as usize * 7..SECTOR_LEN).step_by(61) {
+                list.set(coords(i), BlockId::new(id));
+                expected[i] = BlockId::new(id);
+            }
             
-            let translation = Translation::new((pos.0 * SECTOR_SIZE as i32) as f32,
-                                               (pos.1 * SECTOR_SIZE as i32) as f32,
-                                               (pos.2 * SECTOR_SIZE as i32) as f32);
-                                           
-            //println!("translation: {:?}", translation);
+            let width = match id + 1 {
+                0..=2 => 1,
+                3..=4 => 2,
+                5..=16 => 4,
+                _ => 8,
+            };
+            assert_eq!(bits(&list), Some(width), "with {} blocks", id + 1);
+            assert_blocks(&list, &expected);
+        }
+    }
+    
+    #[test]
+    fn round_trip_at_word_boundaries() {
+        let mut list = BlockList::new_air();
+        let mut expected = vec![BlockId::AIR; SECTOR_LEN];
+        
+        for id in 1..18u8 {
+            let per_word = WORD_BITS / bits(&list).unwrap_or(1);
             
-            Some(Model::with_translation(tess, terrain_tex, translation))
-        } else {
-            None
+            let id_word = id as usize;
+            for word in &[id_word, id_word + 20, SECTOR_LEN / per_word - id_word] {
+                for &i in &[word * per_word - 1, word * per_word, word * per_word + 1] {
+                    let i = i.min(SECTOR_LEN - 1);
+                    
+                    list.set(coords(i), BlockId::new(id));
+                    expected[i] = BlockId::new(id);
+                    assert_blocks(&list, &expected);
+                }
+            }
+        }
+        
+        assert_eq!(bits(&list), Some(8));
+    }
+    
+    #[test]
+    fn iteration_order() {
+        let mut list = BlockList::new_air();
+        for i in 0..SECTOR_LEN {
+            list.set(coords(i), BlockId::new((i % 3) as u8));
         }
+        
+        let mut count = 0;
+        for (i, (pos, block)) in list.into_iter().enumerate() {
+            assert_eq!(pos.index(), i);
+            assert_eq!((pos.x() as usize, pos.y() as usize, pos.z() as usize),
+                       (i % SECTOR_SIZE, i / SECTOR_SIZE % SECTOR_SIZE,
+                        i / (SECTOR_SIZE * SECTOR_SIZE)));
+            assert_eq!(*block, BlockId::new((i % 3) as u8));
+            count += 1;
+        }
+        
+        assert_eq!(count, SECTOR_LEN);
     }
 }
@@ -1,31 +1,231 @@
 //! Procedural world generation.
 
-use noise::{BasicMulti, MultiFractal, NoiseModule};
+use noise::{BasicMulti, MultiFractal, NoiseModule, Perlin, Seedable};
 use super::SECTOR_SIZE;
-use super::voxel::{Block, BlockList, SectorSpaceCoords};
+use super::biome::{self, Biome};
+use super::gen_config::{OreConfig, WorldGenConfig};
+use super::mesh_gen::FACES;
+use super::registry::BlockRegistry;
+use super::voxel::{BlockId, BlockList, SectorSpaceCoords};
 
-const SECTOR_SIZE_F: f32 = SECTOR_SIZE as f32;
+// The depth of grass and loam above the rock.
+const SOIL_DEPTH: i32 = 4;
 
-/// The world generator.
+// The height of a tree above the ground.
+const TREE_HEIGHT: i32 = 8;
+
+// Trees and other features are anchored at one column of blocks,
+// but may reach this many blocks into the columns around it.
+const FEATURE_REACH: i32 = 2;
+
+// Vertical distances count double in the cave
+// noise, so that caves are wider than they are tall.
+const CAVE_SQUASH: f32 = 2.;
+
+/// A source of the blocks in the world.
+///
+/// Generators are shared by the threads that generate sectors,
+/// and must produce the same blocks every time they are asked.
+pub trait WorldGenerator: Send + Sync {
+    /// Generate the blocks of the sector at sector coords.
+    fn generate(&self, sector: (i32, i32, i32)) -> BlockList;
+    
+    /// The height of the ground at **world** block coords,
+    /// which is the y coordinate of the lowest block above
+    /// it that is air or water.
+    fn height_at(&self, x: i32, z: i32) -> i32;
+    
+    /// The range of sector rows, lowest and highest, that hold the
+    /// surface in a column of sectors. Everything below should be
+    /// underground and everything above should be air. Only these
+    /// rows are generated, along with those near the player.
+    ///
+    /// The range only needs to cover this column. The terrain also
+    /// generates the surface rows of the columns next to it and one
+    /// row above and below, so that every sector on the surface has
+    /// the neighbors it needs to be meshed.
+    ///
+    /// By default, the range is found from the height of every
+    /// column of blocks. Generators that place blocks above the
+    /// ground should override it.
+    fn surface_rows(&self, sector_x: i32, sector_z: i32) -> (i32, i32) {
+        let size = SECTOR_SIZE as i32;
+        let mut lowest = i32::MAX;
+        let mut highest = i32::MIN;
+        
+        for z in 0..size {
+            for x in 0..size {
+                let height = self.height_at(sector_x * size + x, sector_z * size + z);
+                
+                lowest = lowest.min(height);
+                highest = highest.max(height);
+            }
+        }
+        
+        (sector_row(lowest - 1), sector_row(highest - 1))
+    }
+}
+
+/// The noise based world generator, with
+/// biomes, caves, ores, seas and trees.
 #[derive(Clone)]
 pub struct WorldGen {
-    //perlin: Perlin,
     base_terrain: BasicMulti<f32>,
     compression: BasicMulti<f32>,
     general_height: BasicMulti<f32>,
     tree: (BasicMulti<f32>, BasicMulti<f32>),
+    temperature: BasicMulti<f32>,
+    humidity: BasicMulti<f32>,
+    tunnels: (Perlin, Perlin),
+    caverns: Perlin,
+    ore_seed: u64,
+    config: WorldGenConfig,
+    blocks: GenBlocks,
+}
+
+// The blocks that the generator places.
+#[derive(Clone)]
+struct GenBlocks {
+    limestone: BlockId,
+    loam: BlockId,
+    grass: BlockId,
+    sand: BlockId,
+    snow: BlockId,
+    tree: BlockId,
+    leaves: BlockId,
+    water: BlockId,
+    
+    // The block of each ore in the settings.
+    ores: Vec<BlockId>,
+    
+    // The blocks that trees may grow into: air,
+    // leaves, and plants, which are neither
+    // solid nor fluid.
+    replaceable: Vec<BlockId>,
+}
+
+impl GenBlocks {
+    fn new(registry: &BlockRegistry, ores: &[OreConfig]) -> GenBlocks {
+        let block = |name| registry.id(name).unwrap_or_else(|| {
+            panic!("world generation needs a block named {:?}", name)
+        });
+        
+        GenBlocks {
+            limestone: block("limestone"),
+            loam: block("loam"),
+            grass: block("grass"),
+            sand: block("sand"),
+            snow: block("snow"),
+            tree: block("tree"),
+            leaves: block("leaves"),
+            water: block("water"),
+            ores: ores.iter().map(|ore| block(&ore.block)).collect(),
+            replaceable: registry.iter()
+                                 .filter(|info| !info.is_solid() && !info.is_fluid())
+                                 .map(|info| info.id())
+                                 .chain(Some(block("leaves")))
+                                 .collect(),
+        }
+    }
+    
+    // The blocks on the surface of a biome,
+    // and the blocks beneath them.
+    fn soil(&self, biome: Biome) -> (BlockId, BlockId) {
+        match biome {
+            Biome::Plains | Biome::Forest => (self.grass, self.loam),
+            Biome::Desert => (self.sand, self.sand),
+            Biome::Mountains => (self.limestone, self.limestone),
+            Biome::Tundra => (self.snow, self.loam),
+        }
+    }
+}
+
+// The ground in one column of blocks.
+#[derive(Clone, Copy)]
+struct Column {
+    height: i32,
+    biome: Biome,
+}
+
+// The ground in every column of blocks in a column of sectors,
+// and in the columns within `FEATURE_REACH` around it.
+struct Columns {
+    columns: Vec<Column>,
+    lowest: i32,
+    highest: i32,
+}
+
+impl Columns {
+    // The width of the area, margins included.
+    const WIDTH: i32 = SECTOR_SIZE as i32 + 2 * FEATURE_REACH;
+    
+    fn new(gen: &WorldGen, sector_x: i32, sector_z: i32) -> Columns {
+        let size = SECTOR_SIZE as i32;
+        let mut columns = Vec::with_capacity((Self::WIDTH * Self::WIDTH) as usize);
+        
+        for z in -FEATURE_REACH..size + FEATURE_REACH {
+            for x in -FEATURE_REACH..size + FEATURE_REACH {
+                columns.push(gen.column_at(sector_x * size + x, sector_z * size + z));
+            }
+        }
+        
+        let lowest = columns.iter().map(|c| c.height).min().unwrap();
+        let highest = columns.iter().map(|c| c.height).max().unwrap();
+        
+        Columns {
+            columns,
+            lowest,
+            highest,
+        }
+    }
+    
+    // The ground at sector coords, which may be
+    // up to `FEATURE_REACH` outside of the sector.
+    fn get(&self, x: i32, z: i32) -> Column {
+        self.columns[((x + FEATURE_REACH) + (z + FEATURE_REACH) * Self::WIDTH) as usize]
+    }
 }
 
 impl WorldGen {
-    /// Create a new `WorldGen`.
-    pub fn new() -> WorldGen {
+    /// Create a new `WorldGen` from a world seed, with the
+    /// default settings. The same seed always produces the
+    /// same world.
+    /// # Panics
+    /// Panics if `registry` lacks a block that the
+    /// generator places.
+    pub fn with_seed(seed: u64, registry: &BlockRegistry) -> WorldGen {
+        WorldGen::with_config(seed, registry, WorldGenConfig::default())
+    }
+    
+    /// Create a new `WorldGen` from a world seed and settings.
+    /// The same seed and settings always produce the same world.
+    /// # Panics
+    /// Panics if `registry` lacks a block that the
+    /// generator places.
+    pub fn with_config(seed: u64, registry: &BlockRegistry,
+                       config: WorldGenConfig) -> WorldGen {
         WorldGen {
-            //perlin: Perlin::new(),
-            base_terrain: BasicMulti::new().set_persistence(0.1),
-            compression: BasicMulti::new().set_persistence(0.05),
-            general_height: BasicMulti::new().set_octaves(4).set_frequency(0.5),
-            tree: (BasicMulti::new().set_frequency(0.01),
-                   BasicMulti::new().set_frequency(1.0)),
+            base_terrain: BasicMulti::new().set_persistence(config.terrain.base_persistence)
+                                           .set_seed(derive_seed(seed, 0)),
+            compression: BasicMulti::new().set_persistence(config.terrain.compression_persistence)
+                                          .set_seed(derive_seed(seed, 1)),
+            general_height: BasicMulti::new().set_octaves(config.terrain.general_octaves)
+                                             .set_frequency(config.terrain.general_frequency)
+                                             .set_seed(derive_seed(seed, 2)),
+            tree: (BasicMulti::new().set_frequency(config.trees.grove_frequency)
+                                    .set_seed(derive_seed(seed, 3)),
+                   BasicMulti::new().set_frequency(config.trees.spacing_frequency)
+                                    .set_seed(derive_seed(seed, 4))),
+            temperature: BasicMulti::new().set_octaves(config.climate.octaves)
+                                          .set_seed(derive_seed(seed, 8)),
+            humidity: BasicMulti::new().set_octaves(config.climate.octaves)
+                                       .set_seed(derive_seed(seed, 9)),
+            tunnels: (Perlin::new().set_seed(derive_seed(seed, 5)),
+                      Perlin::new().set_seed(derive_seed(seed, 6))),
+            caverns: Perlin::new().set_seed(derive_seed(seed, 7)),
+            ore_seed: derive_seed(seed, 10) as u64,
+            blocks: GenBlocks::new(registry, &config.ores),
+            config,
         }
     }
     
@@ -35,141 +235,693 @@ impl WorldGen {
     }
     */
     
-    pub fn generate(&self, sector: (i32, i32, i32)) -> BlockList {
-        /*
-        if sector.1 > 0 {
-            BlockList::new(
-                    [Block::Air;
-                    SECTOR_SIZE * SECTOR_SIZE * SECTOR_SIZE])
-        } else if sector.1 == 0 {
-            BlockList::new(
-                    [Block::Loam;
-                    SECTOR_SIZE * SECTOR_SIZE * SECTOR_SIZE])
+    // Determine if a tree grows from the ground
+    // at **world** block coords.
+    fn has_tree(&self, x: i32, z: i32, biome: Biome) -> bool {
+        let (fx, fz) = (x as f32, z as f32);
+        let trees = &self.config.trees;
+        
+        // Trees grow in groves, which cover more
+        // of the ground where trees are dense.
+        let grove = 1. - trees.grove_growth * self.config.biomes.get(biome).tree_density;
+        
+        self.tree.0.get([fx * trees.grove_scale, fz * trees.grove_scale]) > grove &&
+        self.tree.1.get([fx * trees.spacing_scale, fz * trees.spacing_scale]) >
+            trees.spacing_threshold
+    }
+    
+    // Place the parts of a tree that are inside the sector.
+    // Its base is at sector coords, which may be outside.
+    // The tree only grows into air, leaves and plants, so
+    // it does not cut into the ground or other trunks.
+    fn place_tree(&self, list: &mut BlockList, base: (i32, i32, i32)) {
+        let (x, y, z) = base;
+        let replaceable = &self.blocks.replaceable;
+        
+        for dx in -FEATURE_REACH..FEATURE_REACH + 1 {
+            for dy in 4..TREE_HEIGHT {
+                for dz in -FEATURE_REACH..FEATURE_REACH + 1 {
+                    place(list, (x + dx, y + dy, z + dz), self.blocks.leaves, replaceable);
+                }
+            }
+        }
+        
+        for dy in 0..4 {
+            place(list, (x, y + dy, z), self.blocks.tree, replaceable);
+        }
+    }
+    
+    /// The biome at **world** block coords.
+    pub fn biome_at(&self, x: i32, z: i32) -> Biome {
+        biome::dominant(&self.biome_weights(x, z))
+    }
+    
+    // How much each biome in `BIOMES` weighs at **world** block coords.
+    fn biome_weights(&self, x: i32, z: i32) -> [f32; 5] {
+        let climate = &self.config.climate;
+        let frequency = 1. / climate.size;
+        let point = [x as f32 * frequency, z as f32 * frequency];
+        
+        let temperature = (self.temperature.get(point) * climate.contrast).clamp(-1., 1.);
+        let humidity = (self.humidity.get(point) * climate.contrast).clamp(-1., 1.);
+        
+        biome::weights(temperature, humidity)
+    }
+    
+    // The blocks on the surface of a column, and the blocks beneath
+    // them. Ground near the sea level is a sandy beach, and ground
+    // deeper under water is bare.
+    fn soil(&self, column: Column) -> (BlockId, BlockId) {
+        let water = &self.config.water;
+        let (surface, subsurface) = self.blocks.soil(column.biome);
+        
+        if (column.height - water.sea_level).abs() <= water.beach_height {
+            (self.blocks.sand, self.blocks.sand)
+        } else if column.height < water.sea_level {
+            (subsurface, subsurface)
         } else {
-            BlockList::new(
-                    [Block::Limestone;
-                    SECTOR_SIZE * SECTOR_SIZE * SECTOR_SIZE])
-        }
-        */
-        
-        /*
-        if sector.1 > 0 {
-            BlockList::new(
-                    [Block::Air;
-                    SECTOR_SIZE * SECTOR_SIZE * SECTOR_SIZE])
-        } else if sector.1 == 0 {
-            let mut array = [Block::Air; SECTOR_SIZE * SECTOR_SIZE * SECTOR_SIZE];
-            
-            for x in 0..SECTOR_SIZE {
-                for z in 0..SECTOR_SIZE {
-                    let value = self.perlin.get(
-                            [(x as f32 + SECTOR_SIZE as f32 * sector.0 as f32) * 0.0073,
-                             (z as f32 + SECTOR_SIZE as f32 * sector.2 as f32) * 0.0073]);
-                    
-                    let highest = (value.max(0.) * 40.) as usize + 2;
-                    
-                    for y in 0..highest {
-                        if y == 1 {
-                            array[x + y * SECTOR_SIZE + z * SECTOR_SIZE * SECTOR_SIZE] = Block::Grass;
-                        } else {
-                            if y >= SECTOR_SIZE - 1 {
-                                array[x + y * SECTOR_SIZE + z * SECTOR_SIZE * SECTOR_SIZE] = Block::Limestone;
-                                
-                                break;
+            (surface, subsurface)
+        }
+    }
+    
+    // The ground at **world** block coords. Its height is
+    // blended from the shapes of the biomes that weigh in.
+    fn column_at(&self, x: i32, z: i32) -> Column {
+        let (fx, fz) = (x as f32, z as f32);
+        let terrain = &self.config.terrain;
+        
+        let comp = (self.compression.get([fx * terrain.compression_scale,
+                                          fz * terrain.compression_scale]) +
+                    terrain.compression_offset).min(1.0);
+        
+        let general_h = (self.general_height.get([fx * terrain.general_scale,
+                                                  fz * terrain.general_scale]) +
+                         terrain.general_offset).min(1.0);
+        
+        let height = self.base_terrain.get([fx * terrain.base_scale * comp,
+                                            fz * terrain.base_scale * comp]) * general_h;
+        
+        let weights = self.biome_weights(x, z);
+        let mut blended = 0.;
+        
+        for (&biome, &weight) in biome::BIOMES.iter().zip(weights.iter()) {
+            let shape = self.config.biomes.get(biome);
+            blended += weight * (shape.base_height + height * shape.height_scale);
+        }
+        
+        Column {
+            height: blended.floor() as i32,
+            biome: biome::dominant(&weights),
+        }
+    }
+    
+    // Place the ore veins that reach into a sector. Veins start at
+    // random in every sector, and may wind into the sectors around
+    // it, so those are visited as well. Ore only replaces rock.
+    fn place_ores(&self, list: &mut BlockList, sector: (i32, i32, i32)) {
+        let size = SECTOR_SIZE as i32;
+        
+        for (index, (ore, &block)) in self.config.ores.iter().zip(&self.blocks.ores).enumerate() {
+            for dy in -1..2 {
+                let bottom = (sector.1 + dy) * size;
+                if bottom > ore.max_height || bottom + size <= ore.min_height {
+                    continue;
+                }
+                
+                for dz in -1..2 {
+                    for dx in -1..2 {
+                        let start = (sector.0 + dx, sector.1 + dy, sector.2 + dz);
+                        let mut random = Random::new(self.ore_seed,
+                                                     &[start.0, start.1, start.2, index as i32]);
+                        
+                        let mut veins = ore.veins_per_sector as u32;
+                        if random.fraction() < ore.veins_per_sector.fract() {
+                            veins += 1;
+                        }
+                        
+                        for _ in 0..veins {
+                            // The start of the vein, at sector coords.
+                            let mut pos = (dx * size + random.below(size as u32) as i32,
+                                           dy * size + random.below(size as u32) as i32,
+                                           dz * size + random.below(size as u32) as i32);
+                            
+                            let y = sector.1 * size + pos.1;
+                            if y < ore.min_height || y > ore.max_height {
+                                continue;
                             }
                             
-                            array[x + y * SECTOR_SIZE + z * SECTOR_SIZE * SECTOR_SIZE] =
-                            if y <= highest - 2 {
-                                Block::Grass
-                            } else {
-                                Block::Loam
-                            };
+                            // Each block of the vein is next
+                            // to the one before it.
+                            for _ in 0..ore.vein_size {
+                                self.place_ore(list, pos, block);
+                                
+                                let normal = FACES[random.below(6) as usize].normal();
+                                pos = (pos.0 + normal.0, pos.1 + normal.1, pos.2 + normal.2);
+                            }
                         }
                     }
                 }
             }
-            
-            BlockList::new(array)
-        } else {
-            BlockList::new(
-                    [Block::Limestone;
-                    SECTOR_SIZE * SECTOR_SIZE * SECTOR_SIZE])
         }
-        */
+    }
+    
+    // Replace the rock at sector coords, which may
+    // lie outside of the sector, with ore.
+    fn place_ore(&self, list: &mut BlockList, pos: (i32, i32, i32), block: BlockId) {
+        let size = SECTOR_SIZE as i32;
+        let inside = |c: i32| c >= 0 && c < size;
         
-        if sector.1 == 0 {
-            let mut list = BlockList::new_air();
+        if inside(pos.0) && inside(pos.1) && inside(pos.2) {
+            let pos = SectorSpaceCoords::new(pos.0 as u8, pos.1 as u8, pos.2 as u8);
             
-            for x in 0..SECTOR_SIZE {
-                for z in 0..SECTOR_SIZE {
-                    let (fx, fz) = (x as f32, z as f32);
-                    let (s0, s2) = (sector.0 as f32, sector.2 as f32);
-                    
-                    let comp = (self.compression.get(
-                        [(fx + SECTOR_SIZE_F * s0) * 0.005,
-                         (fz + SECTOR_SIZE_F * s2) * 0.005]) + 1.0).min(1.0);
-                    
-                    //println!("{}", comp);
-                    
-                    let general_h = (self.general_height.get(
-                        [(fx + SECTOR_SIZE_F * s0) * 0.0009,
-                         (fz + SECTOR_SIZE_F * s2) * 0.0009]) + 1.5).min(1.0);
-                    
-                    let height = self.base_terrain.get(
-                        [(fx + SECTOR_SIZE_F * s0) * 0.007 * comp,
-                         (fz + SECTOR_SIZE_F * s2) * 0.007 * comp]) * general_h;
+            if *list.get(pos) == self.blocks.limestone {
+                list.set(pos, block);
+            }
+        }
+    }
+    
+    // Hollow out the caves in a sector, which
+    // are only found deep enough below the surface.
+    fn carve_caves(&self, list: &mut BlockList, sector: (i32, i32, i32), columns: &Columns) {
+        let size = SECTOR_SIZE as i32;
+        let origin = (sector.0 * size, sector.1 * size, sector.2 * size);
+        
+        // Cavern noise is above this threshold in roughly `density`
+        // of the world, and tunnel noise is within this radius of zero
+        // across a width of roughly `width` blocks. The default
+        // spreads were found by measuring the noise.
+        let caves = &self.config.caves;
+        let tunnel_frequency = 1. / caves.tunnel_size;
+        let cavern_frequency = 1. / caves.cavern_size;
+        
+        let density = caves.density.clamp(0., 1.);
+        let cavern_threshold = 1. - caves.cavern_spread * density.sqrt();
+        let tunnel_radius = caves.width.max(0.) * tunnel_frequency * caves.tunnel_spread;
+        
+        if density == 0. && tunnel_radius == 0. {
+            return;
+        }
+        
+        for x in 0..SECTOR_SIZE {
+            for z in 0..SECTOR_SIZE {
+                let deepest = columns.get(x as i32, z as i32).height - caves.min_depth;
+                let (fx, fz) = ((origin.0 + x as i32) as f32, (origin.2 + z as i32) as f32);
+                
+                for y in 0..SECTOR_SIZE {
+                    let world_y = origin.1 + y as i32;
+                    if world_y >= deepest {
+                        break;
+                    }
                     
-                    let middle = SECTOR_SIZE_F / 2.;
+                    let fy = world_y as f32 * CAVE_SQUASH;
                     
-                    let highest = (middle + height * 40.).max(0.).min(SECTOR_SIZE_F) as usize;
+                    let cavern = density > 0. && self.caverns.get(
+                        [fx * cavern_frequency,
+                         fy * cavern_frequency,
+                         fz * cavern_frequency]) > cavern_threshold;
                     
-                    //println!("highest: {}", highest);
+                    let tunnel = !cavern && tunnel_radius > 0. && {
+                        let point = [fx * tunnel_frequency,
+                                     fy * tunnel_frequency,
+                                     fz * tunnel_frequency];
+                        let (a, b) = (self.tunnels.0.get(point), self.tunnels.1.get(point));
+                        
+                        a * a + b * b < tunnel_radius * tunnel_radius
+                    };
                     
-                    for y in 0..highest {
-                        list.set(SectorSpaceCoords::new(x as u8, y as u8, z as u8),
-                                 Block::Grass);
+                    if cavern || tunnel {
+                        list.set(SectorSpaceCoords::new(x as u8, y as u8, z as u8), BlockId::AIR);
                     }
+                }
+            }
+        }
+    }
+}
+
+impl WorldGenerator for WorldGen {
+    fn generate(&self, sector: (i32, i32, i32)) -> BlockList {
+        let size = SECTOR_SIZE as i32;
+        let columns = Columns::new(self, sector.0, sector.2);
+        let bottom = sector.1 * size;
+        
+        let sea_level = self.config.water.sea_level;
+        
+        // Sectors entirely above or below the surface
+        // are the same everywhere.
+        if bottom >= (columns.highest + TREE_HEIGHT).max(sea_level) {
+            return BlockList::new_air();
+        }
+        
+        let mut list = if bottom + size <= columns.lowest - SOIL_DEPTH {
+            BlockList::filled(self.blocks.limestone)
+        } else {
+            let mut list = BlockList::new_air();
+            
+            for x in 0..SECTOR_SIZE {
+                for z in 0..SECTOR_SIZE {
+                    let column = columns.get(x as i32, z as i32);
+                    let height = column.height;
+                    let (surface, subsurface) = self.soil(column);
                     
-                    // Trees
-                    if x >= 3 && x <= SECTOR_SIZE - 3 && z >= 3 && z <= SECTOR_SIZE - 3 && highest < SECTOR_SIZE - 8 {
-                        let tree_chance = self.tree.0.get(
-                            [fx + SECTOR_SIZE_F * s0 * 1.1,
-                             fz + SECTOR_SIZE_F * s2 * 1.1]);
+                    for y in 0..SECTOR_SIZE {
+                        let world_y = bottom + y as i32;
                         
-                        if tree_chance > 0.2 {
-                            let tree_chance2 = self.tree.1.get(
-                                [fx / 2. + SECTOR_SIZE_F * s0 * 1.1,
-                                 fz / 2. + SECTOR_SIZE_F * s2 * 1.1]);
-                            
-                            //println!("{}", tree_chance2);
-                            
-                            if tree_chance2 > 0.25 {
-                                //list.set(SectorSpaceCoords::new(x as u8, highest.min(SECTOR_SIZE - 1) as u8, z as u8),
-                                //         Block::Loam);
-                                
-                                for h in 0..8 {
-                                    list.set(SectorSpaceCoords::new(x as u8, (h + highest) as u8, z as u8),
-                                             Block::Tree);
-                                    for dx in -2i32..3 {
-                                        for dy in 4i32..8 {
-                                            for dz in -2i32..3 {
-                                                list.set(SectorSpaceCoords::new((x as i32 + dx) as u8, (highest as i32 + dy) as u8, (z as i32  + dz) as u8),
-                                                         Block::Leaves);
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
+                        let block = if world_y >= sea_level.max(height) {
+                            break;
+                        } else if world_y >= height {
+                            self.blocks.water
+                        } else if world_y == height - 1 {
+                            surface
+                        } else if world_y >= height - SOIL_DEPTH {
+                            subsurface
+                        } else {
+                            self.blocks.limestone
+                        };
+                        
+                        list.set(SectorSpaceCoords::new(x as u8, y as u8, z as u8), block);
                     }
                 }
             }
             
             list
-        } else if sector.1 == -1 {
-            BlockList::new([Block::Limestone; SECTOR_SIZE * SECTOR_SIZE * SECTOR_SIZE])
-        } else {
-            BlockList::new_air()
+        };
+        
+        self.place_ores(&mut list, sector);
+        self.carve_caves(&mut list, sector, &columns);
+        
+        // Features anchored in the columns around the sector may
+        // reach into it, so they are placed here as well. Each one
+        // only depends on the column it is anchored in, so every
+        // sector it reaches agrees on where it is.
+        for z in -FEATURE_REACH..size + FEATURE_REACH {
+            for x in -FEATURE_REACH..size + FEATURE_REACH {
+                let Column { height, biome } = columns.get(x, z);
+                
+                // Trees do not grow on beaches or under water.
+                if height <= sea_level + self.config.water.beach_height {
+                    continue;
+                }
+                
+                if self.has_tree(sector.0 * size + x, sector.2 * size + z, biome) {
+                    self.place_tree(&mut list, (x, height - bottom, z));
+                }
+            }
+        }
+        
+        list
+    }
+    
+    fn height_at(&self, x: i32, z: i32) -> i32 {
+        self.column_at(x, z).height
+    }
+    
+    // The surface includes the soil, trees and water.
+    fn surface_rows(&self, sector_x: i32, sector_z: i32) -> (i32, i32) {
+        let columns = Columns::new(self, sector_x, sector_z);
+        let sea_level = self.config.water.sea_level;
+        
+        (sector_row(columns.lowest - SOIL_DEPTH),
+         sector_row((columns.highest + TREE_HEIGHT).max(sea_level) - 1))
+    }
+}
+
+// Set a block at sector coords that may lie outside of
+// the sector, in which case nothing is placed. Only the
+// blocks in `replaceable` are replaced.
+fn place(list: &mut BlockList, pos: (i32, i32, i32), block: BlockId, replaceable: &[BlockId]) {
+    let size = SECTOR_SIZE as i32;
+    let inside = |c: i32| c >= 0 && c < size;
+    
+    if inside(pos.0) && inside(pos.1) && inside(pos.2) {
+        let pos = SectorSpaceCoords::new(pos.0 as u8, pos.1 as u8, pos.2 as u8);
+        
+        if replaceable.contains(list.get(pos)) {
+            list.set(pos, block);
+        }
+    }
+}
+
+// The row of the sector containing a **world** y coordinate.
+fn sector_row(y: i32) -> i32 {
+    let size = SECTOR_SIZE as i32;
+    
+    if y >= 0 {
+        y / size
+    } else {
+        (y + 1) / size - 1
+    }
+}
+
+// Derive the seed of an individual noise module from the world
+// seed, so that no two modules share the same permutation.
+// This is the SplitMix64 finalizer. The result is truncated
+// to 32 bits so that worlds are the same on every platform.
+fn derive_seed(seed: u64, module: u64) -> usize {
+    mix(seed.wrapping_add(module.wrapping_add(1).wrapping_mul(GOLDEN_GAMMA))) as u32 as usize
+}
+
+// The SplitMix64 increment.
+const GOLDEN_GAMMA: u64 = 0x9E37_79B9_7F4A_7C15;
+
+// Scramble the bits of a number, with the SplitMix64 finalizer.
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    
+    z ^ (z >> 31)
+}
+
+// Random numbers for features that are placed at random rather
+// than by noise. The numbers depend only on the seed and the
+// position they are made for, so every sector that a feature
+// reaches agrees on where it is. This is SplitMix64.
+struct Random(u64);
+
+impl Random {
+    fn new(seed: u64, position: &[i32]) -> Random {
+        let mut random = Random(seed);
+        
+        for &coord in position {
+            random.0 = random.next() ^ coord as u32 as u64;
+        }
+        
+        random
+    }
+    
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(GOLDEN_GAMMA);
+        
+        mix(self.0)
+    }
+    
+    // A number from 0 up to, but not including, `n`.
+    fn below(&mut self, n: u32) -> u32 {
+        (self.next() % n as u64) as u32
+    }
+    
+    // A number from 0 up to, but not including, 1.
+    fn fraction(&mut self) -> f32 {
+        (self.next() >> 40) as f32 / (1 << 24) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs::File;
+    use super::*;
+    
+    // Columns of sectors far apart from each other, on both
+    // sides of the origin.
+    const COLUMNS: [(i32, i32); 4] = [(0, 0), (3, -7), (-12, 5), (40, 40)];
+    
+    fn registry() -> BlockRegistry {
+        BlockRegistry::load(File::open("data/blocks.toml").unwrap()).unwrap()
+    }
+    
+    // The sectors that hold the surface in every column of `COLUMNS`.
+    fn surface_sectors(gen: &WorldGen) -> Vec<(i32, i32, i32)> {
+        let mut sectors = Vec::new();
+        
+        for &(x, z) in &COLUMNS {
+            let (low, high) = gen.surface_rows(x, z);
+            
+            for y in low..high + 1 {
+                sectors.push((x, y, z));
+            }
+        }
+        
+        sectors
+    }
+    
+    // Determine if two lists hold the same block at every position.
+    fn same_blocks(a: &BlockList, b: &BlockList) -> bool {
+        a.into_iter().zip(b).all(|((_, a), (_, b))| a == b)
+    }
+    
+    #[test]
+    fn same_seed_gives_same_blocks() {
+        let registry = registry();
+        let gen = WorldGen::with_seed(42, &registry);
+        let again = WorldGen::with_seed(42, &registry);
+        
+        for sector in surface_sectors(&gen) {
+            let blocks = gen.generate(sector);
+            
+            assert!(same_blocks(&blocks, &gen.generate(sector)),
+                    "sector {:?} changed when generated twice", sector);
+            assert!(same_blocks(&blocks, &again.generate(sector)),
+                    "sector {:?} changed with a new generator", sector);
+        }
+    }
+    
+    #[test]
+    fn different_seed_gives_different_blocks() {
+        let registry = registry();
+        let gen = WorldGen::with_seed(42, &registry);
+        let other = WorldGen::with_seed(43, &registry);
+        
+        let sectors = surface_sectors(&gen);
+        let differ = sectors.iter().filter(|&&sector| {
+            !same_blocks(&gen.generate(sector), &other.generate(sector))
+        }).count();
+        
+        assert!(differ * 2 > sectors.len(),
+                "only {} of {} sectors differ between seeds", differ, sectors.len());
+    }
+    
+    #[test]
+    fn derived_seeds_differ() {
+        for &seed in &[0, 1, 42, u64::MAX] {
+            let mut seeds: Vec<_> = (0..11).map(|module| derive_seed(seed, module)).collect();
+            seeds.sort();
+            seeds.dedup();
+            
+            assert_eq!(seeds.len(), 11, "two modules share a seed for world seed {}", seed);
+        }
+    }
+    
+    // The default settings without caves, which would
+    // hollow out some of the ore.
+    fn without_caves() -> WorldGenConfig {
+        let mut config = WorldGenConfig::default();
+        config.caves.density = 0.;
+        config.caves.width = 0.;
+        
+        config
+    }
+    
+    // Count the blocks of each kind in the sectors.
+    fn count_blocks(gen: &WorldGen, sectors: &[(i32, i32, i32)]) -> HashMap<BlockId, usize> {
+        let mut counts = HashMap::new();
+        
+        for &sector in sectors {
+            for (_, &block) in &gen.generate(sector) {
+                *counts.entry(block).or_insert(0) += 1;
+            }
+        }
+        
+        counts
+    }
+    
+    #[test]
+    fn ore_counts_match_settings() {
+        let registry = registry();
+        let config = without_caves();
+        let gen = WorldGen::with_config(7, &registry, config.clone());
+        let size = SECTOR_SIZE as i32;
+        
+        for ore in &config.ores {
+            // A row deep underground, with rows above and
+            // below it that are also within the range.
+            let row = sector_row(ore.min_height + size - 1) + 1;
+            assert!((row + 2) * size - 1 <= ore.max_height);
+            
+            let sectors: Vec<_> = (0..36).map(|i| (i % 6 - 3, row, i / 6 - 3)).collect();
+            let count = count_blocks(&gen, &sectors)[&registry.id(&ore.block).unwrap()];
+            
+            // A vein places at most `vein_size` blocks, fewer where
+            // it turns back on itself, and veins that leave the
+            // sector are made up for by those that enter it.
+            let most = ore.veins_per_sector * ore.vein_size as f32;
+            let average = count as f32 / sectors.len() as f32;
+            
+            assert!(average <= most && average >= most / 2.,
+                    "{} blocks of {} per sector, expected at most {}", average, ore.block, most);
+        }
+    }
+    
+    #[test]
+    fn ore_stays_within_heights() {
+        let registry = registry();
+        let mut config = without_caves();
+        config.ores = vec![OreConfig {
+            block: "coal_ore".to_string(),
+            min_height: -200,
+            max_height: -150,
+            veins_per_sector: 4.,
+            vein_size: 10,
+        }];
+        
+        let gen = WorldGen::with_config(7, &registry, config);
+        let coal = registry.id("coal_ore").unwrap();
+        let size = SECTOR_SIZE as i32;
+        
+        // A vein reaches at most `vein_size - 1` blocks
+        // above or below the block it starts at.
+        let (lowest, highest) = (-200 - 9, -150 + 9);
+        let mut found = 0;
+        
+        for z in -2..2 {
+            for x in -2..2 {
+                for row in -8..-3 {
+                    for (pos, &block) in &gen.generate((x, row, z)) {
+                        let y = row * size + pos.y() as i32;
+                        
+                        if block == coal {
+                            assert!(y >= lowest && y <= highest, "coal at height {}", y);
+                            found += 1;
+                        }
+                    }
+                }
+            }
+        }
+        
+        assert!(found > 0, "no coal was placed");
+    }
+    
+    #[test]
+    fn ore_only_replaces_rock() {
+        let registry = registry();
+        let config = WorldGenConfig::default();
+        let mut no_ores = config.clone();
+        no_ores.ores.clear();
+        
+        let gen = WorldGen::with_config(7, &registry, config);
+        let rock = WorldGen::with_config(7, &registry, no_ores);
+        let limestone = registry.id("limestone").unwrap();
+        let ores: Vec<_> = gen.config.ores.iter().map(|ore| registry.id(&ore.block).unwrap())
+                                                 .collect();
+        
+        // The surface, where ore may meet soil, air and
+        // water, and a row deep underground.
+        let mut sectors = surface_sectors(&gen);
+        sectors.extend(COLUMNS.iter().map(|&(x, z)| (x, -4, z)));
+        
+        for sector in sectors {
+            let with_ores = gen.generate(sector);
+            let without = rock.generate(sector);
+            
+            for ((pos, &block), (_, &before)) in with_ores.into_iter().zip(&without) {
+                if ores.contains(&block) {
+                    assert!(before == limestone, "ore replaced {:?} at {:?} in {:?}",
+                            before, pos, sector);
+                } else {
+                    assert!(block == before, "{:?} changed at {:?} in {:?}", before, pos, sector);
+                }
+            }
         }
     }
+    
+    #[test]
+    fn trees_only_grow_into_air_and_plants() {
+        let registry = registry();
+        let gen = WorldGen::with_seed(7, &registry);
+        let id = |name| registry.id(name).unwrap();
+        let at = |x, y, z| SectorSpaceCoords::new(x, y, z);
+        
+        // Ground up to y = 15, a flower and another trunk where
+        // the leaves grow, and leaves where the trunk grows.
+        let mut list = BlockList::new_air();
+        for (pos, _) in &BlockList::new_air() {
+            if pos.y() < 16 {
+                list.set(pos, id("loam"));
+            }
+        }
+        
+        list.set(at(11, 18, 10), id("flower"));
+        list.set(at(9, 18, 10), id("tree"));
+        list.set(at(10, 16, 10), id("leaves"));
+        
+        gen.place_tree(&mut list, (10, 14, 10));
+        
+        assert_eq!(*list.get(at(10, 14, 10)), id("loam"));
+        assert_eq!(*list.get(at(10, 15, 10)), id("loam"));
+        assert_eq!(*list.get(at(10, 16, 10)), id("tree"));
+        assert_eq!(*list.get(at(10, 17, 10)), id("tree"));
+        assert_eq!(*list.get(at(10, 18, 10)), id("leaves"));
+        assert_eq!(*list.get(at(11, 18, 10)), id("leaves"));
+        assert_eq!(*list.get(at(9, 18, 10)), id("tree"));
+        assert_eq!(*list.get(at(12, 21, 12)), id("leaves"));
+        assert_eq!(*list.get(at(10, 22, 10)), BlockId::AIR);
+    }
+    
+    #[test]
+    fn caves_stay_below_surface() {
+        let registry = registry();
+        let config = WorldGenConfig::default();
+        let min_depth = config.caves.min_depth;
+        let gen = WorldGen::with_config(7, &registry, config);
+        let solid = WorldGen::with_config(7, &registry, without_caves());
+        let size = SECTOR_SIZE as i32;
+        
+        let mut sectors = surface_sectors(&gen);
+        sectors.extend(COLUMNS.iter().map(|&(x, z)| (x, -3, z)));
+        
+        let mut carved = 0;
+        for sector in sectors {
+            let with_caves = gen.generate(sector);
+            let without = solid.generate(sector);
+            
+            for ((pos, &block), (_, &before)) in with_caves.into_iter().zip(&without) {
+                if block == before {
+                    continue;
+                }
+                
+                let x = sector.0 * size + pos.x() as i32;
+                let y = sector.1 * size + pos.y() as i32;
+                let z = sector.2 * size + pos.z() as i32;
+                
+                assert!(block.is_air(), "a cave placed {:?} at {:?}", block, (x, y, z));
+                assert!(y < gen.height_at(x, z) - min_depth,
+                        "a cave reached {:?}, with the ground at {}", (x, y, z),
+                        gen.height_at(x, z));
+                carved += 1;
+            }
+        }
+        
+        assert!(carved > 0, "no caves were carved");
+    }
+    
+    #[test]
+    fn water_fills_up_to_sea_level() {
+        let registry = registry();
+        let mut config = WorldGenConfig::default();
+        config.water.sea_level = 40;
+        let gen = WorldGen::with_config(7, &registry, config);
+        let water = registry.id("water").unwrap();
+        let size = SECTOR_SIZE as i32;
+        
+        let mut found = 0;
+        for sector in surface_sectors(&gen) {
+            for (pos, &block) in &gen.generate(sector) {
+                let x = sector.0 * size + pos.x() as i32;
+                let y = sector.1 * size + pos.y() as i32;
+                let z = sector.2 * size + pos.z() as i32;
+                let height = gen.height_at(x, z);
+                
+                // Water lies over the ground, up to the
+                // block below the sea level.
+                if y >= height && y < 40 {
+                    assert!(block == water, "{:?} under the sea at {:?}", block, (x, y, z));
+                    found += 1;
+                } else {
+                    assert!(block != water, "water at {:?}, with the ground at {}",
+                            (x, y, z), height);
+                }
+            }
+        }
+        
+        assert!(found > 0, "no water was placed");
+    }
 }
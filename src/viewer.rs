@@ -1,5 +1,10 @@
 //! The main entry point.
 
+use std::fs::{self, File};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Instant;
 use glfw::CursorMode;
 use luminance::framebuffer::Framebuffer;
@@ -9,29 +14,106 @@ use luminance_glfw::{Action, Device, GLFWDevice, GLFWDeviceError, Key,
 use camera::{Camera, MovementDirection};
 use model::Drawable;
 use resources::Resources;
-use terrain::Terrain;
+use terrain::{DebugWorld, Edges, Heightmap, HeightmapOptions, HeightmapWorld, Superflat,
+              Terrain, WorldGen, WorldGenConfig, WorldGenerator};
 
 const SCREEN_SIZE: (u32, u32) = (800, 800);
 const SPEED: f32 = 15.;
 const FAST_MULTIPLIER: f32 = 5.;
 const SENSITIVITY: f32 = 0.1;
 
+// Each world is saved in a directory named after its seed and
+// settings, its heightmap, or its kind if it has neither, in this one.
+const SAVE_DIR: &str = "worlds";
+
+// The settings of the noise based world generator.
+const WORLDGEN_CONFIG: &str = "data/worldgen.toml";
+
+/// The kinds of world that can be viewed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum WorldKind {
+    /// The noise based world, generated from the seed.
+    Noise,
+    
+    /// A flat world of layers of blocks.
+    Superflat,
+    
+    /// A floor with every block standing on it.
+    Debug,
+    
+    /// Terrain that follows a heightmap file,
+    /// which is a PNG image or an ESRI ASCII grid.
+    Heightmap(HeightmapFile),
+}
+
+impl FromStr for WorldKind {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<WorldKind, String> {
+        match s {
+            "noise" => Ok(WorldKind::Noise),
+            "superflat" => Ok(WorldKind::Superflat),
+            "debug" => Ok(WorldKind::Debug),
+            _ if s.ends_with(".png") || s.ends_with(".asc") => {
+                Ok(WorldKind::Heightmap(HeightmapFile::new(PathBuf::from(s))))
+            },
+            _ => Err(format!("unknown kind of world {:?}", s)),
+        }
+    }
+}
+
+/// A heightmap file to view, and how it is scaled.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeightmapFile {
+    /// The path of the file.
+    pub path: PathBuf,
+    
+    /// The width of one sample, in blocks.
+    pub horizontal_scale: f32,
+    
+    /// The height of one unit of a sample, in blocks. By default
+    /// it is 64 for images, whose samples go from 0 to 1, and 1 for
+    /// ASCII grids, whose samples are elevations.
+    pub vertical_scale: Option<f32>,
+    
+    /// What lies beyond the edges of the heightmap.
+    pub edges: Edges,
+}
+
+impl HeightmapFile {
+    /// View the heightmap at `path` with the default scales,
+    /// clamped at the edges.
+    pub fn new(path: PathBuf) -> HeightmapFile {
+        HeightmapFile {
+            path,
+            horizontal_scale: 1.,
+            vertical_scale: None,
+            edges: Edges::Clamp,
+        }
+    }
+}
+
 /// The core of the app, manages the program.
 pub struct Viewer {
     device: GLFWDevice,
     render_target: Framebuffer<Flat, Dim2, (), ()>,
     camera: Camera,
+    seed: u64,
+    kind: WorldKind,
 }
 
 impl Viewer {
-    /// Start up!
-    pub fn run() {
+    /// Start up! The world is generated from `seed`,
+    /// if it is of a kind that has one.
+    pub fn run(seed: u64, kind: WorldKind) {
         let device = Self::create_device().unwrap();
         
         Viewer {
             device,
             render_target: Framebuffer::default([SCREEN_SIZE.0, SCREEN_SIZE.1]),
             camera: Camera::new(SCREEN_SIZE),
+            seed,
+            kind,
         }.start();
     }
     
@@ -46,9 +128,59 @@ impl Viewer {
         
         self.device.lib_handle_mut().set_cursor_mode(CursorMode::Disabled);
         
-        self.camera.translation_mut().y = 50.;
+        let registry = resources.block_registry();
+        let (gen, name): (Arc<dyn WorldGenerator>, _) = match self.kind.clone() {
+            WorldKind::Noise => {
+                let config = File::open(WORLDGEN_CONFIG).and_then(|file| {
+                    WorldGenConfig::load(file, registry)
+                });
+                let config = config.unwrap_or_else(|e| {
+                    panic!("Could not load {}: {}", WORLDGEN_CONFIG, e)
+                });
+                
+                // The same seed with other settings is a different world.
+                let name = format!("{}-{:016x}", self.seed,
+                                   hash(format!("{:?}", config).as_bytes()));
+                
+                (Arc::new(WorldGen::with_config(self.seed, registry, config)), name)
+            },
+            WorldKind::Superflat => (Arc::new(Superflat::classic(registry)),
+                                     "superflat".to_string()),
+            WorldKind::Debug => (Arc::new(DebugWorld::new(registry)),
+                                 "debug".to_string()),
+            WorldKind::Heightmap(file) => {
+                let map = Heightmap::open(&file.path).unwrap_or_else(|e| {
+                    panic!("Could not load {}: {}", file.path.display(), e)
+                });
+                
+                // The cells of ASCII grids are elevations,
+                // which are used as heights in blocks.
+                let mut options = HeightmapOptions::new(registry);
+                if file.path.extension() == Some(OsStr::new("asc")) {
+                    options.vertical_scale = 1.;
+                }
+                
+                options.vertical_scale = file.vertical_scale.unwrap_or(options.vertical_scale);
+                options.horizontal_scale = file.horizontal_scale;
+                options.edges = file.edges;
+                
+                // Other heightmaps with the same name, and the same
+                // heightmap at other scales, are different worlds.
+                let mut data = fs::read(&file.path).unwrap_or_default();
+                data.extend_from_slice(format!("{:?}", options).as_bytes());
+                
+                let name = format!("heightmap-{}-{:016x}",
+                                   file.path.file_stem().unwrap().to_string_lossy(), hash(&data));
+                
+                (Arc::new(HeightmapWorld::new(map, options)), name)
+            },
+        };
+        
+        let save_dir = Path::new(SAVE_DIR).join(name);
+        let mut terrain = Terrain::new(&resources, gen, &save_dir);
         
-        let mut terrain = Terrain::new(&resources);
+        // Start a little above the ground.
+        self.camera.translation_mut().y = terrain.ground_height(0, 0) as f32 + 18.;
         
         /*
         let test1 = mat4! [
@@ -93,6 +225,8 @@ impl Viewer {
             
             //::std::thread::sleep(::std::time::Duration::from_millis(10));
         }
+        
+        terrain.save();
     }
     
     // #Return Value
@@ -191,3 +325,11 @@ impl Viewer {
         self.device.lib_handle_mut().set_cursor_pos(0., 0.);
     }
 }
+
+// Hash data into a name that stays the same from run to run,
+// by 64 bit FNV-1a.
+fn hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01B3)
+    })
+}